@@ -17,20 +17,25 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use chrono::{Utc, NaiveDate};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
+use futures_lite::{io::BufWriter, AsyncWrite, AsyncWriteExt};
 use regex::Regex;
 
-use std::{collections::{BTreeMap, BTreeSet}, ops::RangeInclusive};
+use std::{collections::{btree_set, BTreeMap, BTreeSet}, ops::{Deref, RangeInclusive}};
+use std::io;
 use std::sync::LazyLock;
 use std::iter;
 
 use crate::conf::SubFull;
-use crate::booies_cache::BooiesDetailsCache;
+use crate::cli::{ListFormat, LoadMode};
+use crate::booies_cache::{BooiesDetailsCache, BooiesDetailsCacheOf};
 
-use crate::storage_util::{IsSpeedyRwRd, StorageInfo};
-use crate::deserialize_util::{YearOrYMD, MapOrSeq};
+use crate::storage_util::{IsSpeedyRwRd, IsSpeedyView, StorageInfo};
+use crate::deserialize_util::{MapOrSeq, Opt, YearOrYMD};
 use crate::wrapper_types::NDWrapper;
+use crate::phase_util::{Phase, PhaseStats};
 
 use crate::booies::{Booies, BooiesExample, BooiesExampleInfo, BooiesExampleSadioInfo, BooiesExampleFigureInfo, BooiesDetails};
 
@@ -38,23 +43,23 @@ type CCI = CommonConfInfo;
 
 use crate::AllocPerfRes;
 
-#[derive(Readable, Writable)]
-pub(crate) struct BooiesIndex {
-    categories: BTreeMap<u64, String>,
-    list: BTreeMap<u64, Booies>,
+#[derive(Readable, Writable, Serialize, Deserialize)]
+pub(crate) struct BooiesIndex<S> {
+    categories: BTreeMap<u64, S>,
+    list: BTreeMap<u64, Booies<S>>,
     category_booies_map: BTreeMap<u64, BTreeSet<u64>>,
     uncategoriezed_booies_nums: BTreeSet<u64>,
 }
 
-impl BooiesIndex {
-    fn gen_random(sz: usize) -> Self {
-        fn gen_unique_ids(range_val: RangeInclusive<u64>, range_count: RangeInclusive<usize>) -> impl Iterator<Item=u64> {
-            iter::repeat_with(|| fastrand::u64(range_val.clone()))
+impl BooiesIndex<String> {
+    fn gen_random(sz: usize, rng: &mut fastrand::Rng) -> Self {
+        fn gen_unique_ids(rng: &mut fastrand::Rng, range_val: RangeInclusive<u64>, range_count: RangeInclusive<usize>) -> iter::Take<btree_set::IntoIter<u64>> {
+            let ids = iter::repeat_with(|| rng.u64(range_val.clone()))
                 .take(2*{*range_count.end()})
                 // dedup
-                .collect::<BTreeSet<_>>()
-                .into_iter()
-                .take(fastrand::usize(range_count))
+                .collect::<BTreeSet<_>>();
+            ids.into_iter()
+                .take(rng.usize(range_count))
         }
 
         let uncategoriezed_booies_nums = BTreeSet::new();
@@ -62,34 +67,34 @@ impl BooiesIndex {
         let lower = {sz*75/100}.max(1);
         let upper = {sz*125/100};
 
-        let categories: BTreeMap<_,_> = gen_unique_ids(1000..=10000, lower..=upper)
+        let categories: BTreeMap<_,_> = gen_unique_ids(rng, 1000..=10000, lower..=upper)
             .map(|cat_id| (
                 cat_id,
-                itertools::join({2..=4}.map(|_| crate::rand_str(3..=32)), " "),
+                itertools::join({2..=4}.map(|_| crate::rand_str(rng, 3..=32)), " "),
             )).collect();
 
-        let list = gen_unique_ids(10001..=100000, lower*categories.len()..=upper*categories.len())
+        let list = gen_unique_ids(rng, 10001..=100000, lower*categories.len()..=upper*categories.len())
             .map(|booies_id| {
                 let category_id = categories
                     .keys()
-                    .nth(fastrand::usize(..categories.len()))
+                    .nth(rng.usize(..categories.len()))
                     .copied()
                     .expect("impossible");
                 Booies {
                     booies_id,
                     num: booies_id,
-                    name: itertools::join({2..=6}.map(|_| crate::rand_str(3..=32)), " "),
-                    last_modified: fastrand::i64(1700000000..=1720000000),
-                    genre: crate::rand_str(8..=24),
+                    name: itertools::join({2..=6}.map(|_| crate::rand_str(rng, 3..=32)), " "),
+                    last_modified: rng.i64(1700000000..=1720000000),
+                    genre: crate::rand_str(rng, 8..=24),
                     release_date: NaiveDate::from_ymd_opt(
                         // allow out-of-range values that will turn to `None`s
-                        fastrand::i32(2000..=2023),
-                        fastrand::u32(1..=12),
-                        fastrand::u32(1..=31),
+                        rng.i32(2000..=2023),
+                        rng.u32(1..=12),
+                        rng.u32(1..=31),
                     ).map(|nd| YearOrYMD::YMD(NDWrapper(nd))),
                     category_id: Some(category_id),
                     category_ids: Some(vec![category_id]),
-                    rating: Some(fastrand::f64()*10.0),
+                    rating: Some(rng.f64()*10.0),
                 }
             }).map(|boo| (boo.booies_id, boo)).collect::<BTreeMap<_,_>>();
 
@@ -109,21 +114,26 @@ impl BooiesIndex {
     }
 }
 
-#[derive(Readable, Writable)]
-pub(crate) struct AllInfo {
+#[derive(Readable, Writable, Serialize, Deserialize)]
+pub(crate) struct AllInfoOf<S> {
     fetched_at: i64,
-    booies_index: Option<BooiesIndex>,
+    booies_index: Opt<BooiesIndex<S>>,
 }
 
+pub(crate) type AllInfo = AllInfoOf<String>;
+/// `AllInfo` with strings borrowed from serialized data.
+pub(crate) type AllInfoView<'a> = AllInfoOf<&'a str>;
+
 impl AllInfo {
-    pub(crate) fn gen_random(sz: usize) -> Self {
+    pub(crate) fn gen_random(sz: usize, rng: &mut fastrand::Rng) -> Self {
         Self {
-            fetched_at: Utc::now().timestamp(),
-            booies_index: Some(BooiesIndex::gen_random(sz)),
+            // not `now()`, so generated data is reproducible with the same seed
+            fetched_at: rng.i64(1720000000..=1730000000),
+            booies_index: Some(BooiesIndex::gen_random(sz, rng)),
         }
     }
 
-    pub(crate) fn gen_booies_details(&self) -> BooiesDetailsCache {
+    pub(crate) fn gen_booies_details(&self, rng: &mut fastrand::Rng) -> BooiesDetailsCache {
         let mut cache = BooiesDetailsCache::new();
         let mut id_iter = 500_000_u64..;
         for boo in self.booies_index
@@ -133,16 +143,16 @@ impl AllInfo {
             .flatten()
         {
             let mut examples_chapters = Vec::with_capacity(12);
-            for chapter in 1..=fastrand::u64(1..=12) {
+            for chapter in 1..=rng.u64(1..=12) {
                 let mut examples = Vec::with_capacity(24);
-                for example_num in 1..=fastrand::u64(6..=24) {
-                    let duration_secs = fastrand::u64(600..=5000);
+                for example_num in 1..=rng.u64(6..=24) {
+                    let duration_secs = rng.u64(600..=5000);
                     let example = BooiesExample {
                         id: id_iter.next().expect("impossible"),
                         chapter: Some(chapter),
                         example_num: Some(example_num),
-                        title: itertools::join({2..=6}.map(|_| crate::rand_str(3..=32)), " "),
-                        container_extension: crate::rand_str(2..=4),
+                        title: itertools::join({2..=6}.map(|_| crate::rand_str(rng, 3..=32)), " "),
+                        container_extension: crate::rand_str(rng, 2..=4),
                         added: 1720000000,
                         info: Some(BooiesExampleInfo {
                             duration_secs: Some(duration_secs),
@@ -150,7 +160,7 @@ impl AllInfo {
                                     duration_secs/3600,
                                     (duration_secs%3600)/60,
                                     duration_secs%60)),
-                                    bitrate: Some(fastrand::u64(1000..=15000)),
+                                    bitrate: Some(rng.u64(1000..=15000)),
                                     sadio: Some(BooiesExampleSadioInfo{
                                         boec_name: Some("SAD".into()),
                                         bad_rate: 20000,
@@ -168,11 +178,34 @@ impl AllInfo {
                 examples_chapters.push(examples);
             }
             let boo_details = BooiesDetails{ examples: Some(MapOrSeq::Seq(examples_chapters)) };
-            cache.insert(boo.num, boo_details);
+            cache.insert(boo.num, boo_details, self.fetched_at);
         }
         cache
     }
 
+    /// Counts and totals printed by `inspect`.
+    pub(crate) fn summary(&self) -> Vec<(&'static str, String)> {
+        let mut summary = vec![("fetched at", crate::inspect::fmt_timestamp(self.fetched_at))];
+        match &self.booies_index {
+            None => summary.push(("booies index", "none".into())),
+            Some(booies_index) => {
+                let BooiesIndex { categories, list, category_booies_map, uncategoriezed_booies_nums } = booies_index;
+                let str_bytes = categories.values().map(String::len).sum::<usize>()
+                    + list.values().map(|boo| boo.name.len() + boo.genre.len()).sum::<usize>();
+                summary.extend([
+                    ("categories", categories.len().to_string()),
+                    ("non-empty categories", category_booies_map.values().filter(|nums| !nums.is_empty()).count().to_string()),
+                    ("booies", list.len().to_string()),
+                    ("uncategorized booies", uncategoriezed_booies_nums.len().to_string()),
+                    ("string bytes", str_bytes.to_string()),
+                ]);
+            },
+        }
+        summary
+    }
+}
+
+impl<S> AllInfoOf<S> {
     fn mk_cci(&self, sub: &SubFull) -> CCI {
         let full_server_url = sub.domain.clone();
         let username = sub.username.clone();
@@ -190,6 +223,10 @@ impl IsSpeedyRwRd for AllInfo {
     const FILE_NAME: &'static str = "ALL";
 }
 
+impl IsSpeedyView for AllInfo {
+    type View<'a> = AllInfoView<'a>;
+}
+
 pub(crate) struct CommonConfInfo {
     full_server_url: String,
     username: String,
@@ -200,64 +237,94 @@ pub(crate) struct CommonConfInfo {
 pub(crate) struct ExtractedBooiesExampleStweem {
     name: String,
     url: String,
+    duration_secs: Option<u64>,
+    /// name of the booies category
+    group: Option<String>,
 }
 
 pub(crate) struct EBESMap(BTreeMap<u64, Vec<ExtractedBooiesExampleStweem>>);
 
 impl EBESMap {
-    fn _final_filtered_list<'a>(booies_index: &'a BooiesIndex) -> impl Iterator<Item=&'a Booies> + 'a {
+    fn _final_filtered_list<S>(booies_index: &BooiesIndex<S>) -> impl Iterator<Item=&Booies<S>> {
         booies_index.list.values()
     }
 
-    async fn mk(booies_index: &BooiesIndex, cci: &CCI) -> AllocPerfRes<Self> {
-        let filtered_list = Self::_final_filtered_list(booies_index);
-
-        let cache = BooiesDetailsCache::get_local_or_new(&cci.sub).await?;
+    async fn mk<S: Deref<Target=str>>(booies_index: &BooiesIndex<S>, cci: &CCI, load_mode: LoadMode, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
+        let inner_res = match load_mode {
+            LoadMode::Mmap => {
+                // the cache view borrows from the mapping, so both are kept until the e-map is built
+                let mapped = BooiesDetailsCache::map_local(&cci.sub, stats).await?;
+                let cache = BooiesDetailsCache::view_mapped(&mapped, stats)?;
+                let inner_res = stats.record(Phase::EBESMapBuild, || Self::build(booies_index, cci, &cache));
+                stats.record(Phase::Teardown, || drop(cache));
+                stats.record(Phase::Teardown, || drop(mapped));
+                inner_res
+            },
+            LoadMode::Read | LoadMode::Stream => {
+                let cache = BooiesDetailsCache::get_local(&cci.sub, load_mode, stats).await?;
+                let inner_res = stats.record(Phase::EBESMapBuild, || Self::build(booies_index, cci, &cache));
+                stats.record(Phase::Teardown, || drop(cache));
+                inner_res
+            },
+        };
+        Ok(Self(inner_res?))
+    }
 
-        let inner = filtered_list
+    fn build<S, T>(booies_index: &BooiesIndex<S>, cci: &CCI, cache: &BooiesDetailsCacheOf<T>) -> AllocPerfRes<BTreeMap<u64, Vec<ExtractedBooiesExampleStweem>>>
+        where S: Deref<Target=str>,
+              T: Deref<Target=str>,
+    {
+        let filtered_list = Self::_final_filtered_list(booies_index);
+        filtered_list
             .map(|boo| boo.num)
             .filter_map(|num| {
                 let boo_details = cache.get_boo_details(num);
 
                 if boo_details.is_none() {
-                    tracing::error!("details of booies '{}' not in cache", booies_index.list[&num].name);
+                    tracing::error!("details of booies '{}' not in cache", &*booies_index.list[&num].name);
                 }
                 boo_details.and_then(|boo_det| boo_det.examples.as_ref().map(|examples| (num, examples)))
             })
             .map(|(num, examples)| {
+                let booies = &booies_index.list[&num];
+                let group = booies.category_id
+                    .and_then(|category_id| booies_index.categories.get(&category_id))
+                    .map(|category| &**category);
                 Ok((
                     num,
                     examples.values()
                         .flatten()
                         .into_iter()
-                        .map(|example| Self::mk_extracted_st(&cci, &booies_index.list[&num], &example))
+                        .map(|example| Self::mk_extracted_st(&cci, booies, group, &example))
                         .collect::<AllocPerfRes<Vec<_>>>()?,
                  ))
             })
-            .collect::<AllocPerfRes<BTreeMap<_, _>>>()?;
-        Ok(Self(inner))
+            .collect::<AllocPerfRes<BTreeMap<_, _>>>()
     }
 }
 
 impl EBESMap {
-    pub(crate) async fn mk_from_all(all: &AllInfo, sub: &SubFull) -> AllocPerfRes<Option<Self>> {
+    pub(crate) async fn mk_from_all<S: Deref<Target=str>>(all: &AllInfoOf<S>, sub: &SubFull, load_mode: LoadMode, stats: &mut PhaseStats) -> AllocPerfRes<Option<Self>> {
         let cci = all.mk_cci(&sub);
         match all.booies_index.as_ref() {
             None => Ok(None),
             Some(booies_index) => {
-                Ok(Some(Self::mk(&booies_index, &cci).await?))
+                Ok(Some(Self::mk(&booies_index, &cci, load_mode, stats).await?))
             },
         }
     }
 
-    fn mk_extracted_st(cci: &CCI, booies: &Booies, example: &BooiesExample) -> AllocPerfRes<ExtractedBooiesExampleStweem> {
+    fn mk_extracted_st<S, T>(cci: &CCI, booies: &Booies<S>, group: Option<&str>, example: &BooiesExample<T>) -> AllocPerfRes<ExtractedBooiesExampleStweem>
+        where S: Deref<Target=str>,
+              T: Deref<Target=str>,
+    {
         let server = &*cci.full_server_url;
 
         let user = &cci.username;
         let pass = &cci.password;
 
         let id = example.id;
-        let ext = &example.container_extension;
+        let ext = &*example.container_extension;
         let url = format!("booies|{server}|{user}|{pass}|{id}.{ext}");
 
         let name_extra = example
@@ -293,8 +360,8 @@ impl EBESMap {
 
                 let b_kibs = example_info.bitrate.as_ref().map(|b| format!("{b} kibs"));
 
-                let dur_hms = example_info.duration.as_ref()
-                    .map(Clone::clone)
+                let dur_hms = example_info.duration.as_deref()
+                    .map(str::to_owned)
                     .or_else(|| example_info.duration_secs.map(|dur_secs| {
                         let h = dur_secs / 3600;
                         let m = (dur_secs % 3600) / 60;
@@ -317,46 +384,242 @@ impl EBESMap {
 
         let name = match (example.chapter, example.example_num) {
             (Some(c), Some(n)) => {
-                format!("{} C{c:02}N{n:02}{name_extra}", booies.name)
+                format!("{} C{c:02}N{n:02}{name_extra}", &*booies.name)
             },
             _ => {
                 let cap_opt = C_N_CAP.captures(&example.title);
                 if let Some(cap) = cap_opt &&
                     let (Some(Ok(c)), Some(Ok(n))) = (cap.get(1).map(|c| c.as_str().parse::<u64>()), cap.get(3).map(|n| n.as_str().parse::<u64>()))
                 {
-                    tracing::warn!("chapter and example_num info missing from example, guessing from example title: '{}'", example.title);
+                    tracing::warn!("chapter and example_num info missing from example, guessing from example title: '{}'", &*example.title);
                     tracing::warn!("Guessed example C/N: C{c:02}N{n:02}");
-                    format!("{} C{c:02}N{n:02}{name_extra}", booies.name)
+                    format!("{} C{c:02}N{n:02}{name_extra}", &*booies.name)
                 } else {
                     tracing::warn!("chapter and example_num info missing, using example title");
-                    format!("{} {}{name_extra}", example.title, booies.name)
+                    format!("{} {}{name_extra}", &*example.title, &*booies.name)
                 }
             },
         };
 
-        Ok(ExtractedBooiesExampleStweem { name, url })
+        let duration_secs = example.info.as_ref().and_then(|example_info| example_info.duration_secs);
+        let group = group.map(ToOwned::to_owned);
 
+        Ok(ExtractedBooiesExampleStweem { name, url, duration_secs, group })
+
+    }
+    pub(crate) fn entry_count(&self) -> usize {
+        self.0.values().map(Vec::len).sum()
     }
-    pub(crate) fn multi_to_formatted_list<'a>(multi: &'a [Option<Self>]) -> String {
+
+    pub(crate) fn multi_to_formatted_list<'a>(multi: &'a [(u8, Option<Self>)], format: ListFormat) -> String {
         let flat_e_st_list = multi
             .iter()
-            .filter_map(Option::as_ref)
+            .filter_map(|(_, e_map_opt)| e_map_opt.as_ref())
             .map(|e_st_map| e_st_map.0.values())
             .map(|e_vecs| e_vecs.into_iter().flatten())
             .flatten()
             .collect::<Vec<_>>();
         let mut ret = String::with_capacity(flat_e_st_list.len()*512);
-        ret.push_str("##Random Text File Format Header##");
+        ret.push_str(list_header(format));
         flat_e_st_list
             .into_iter()
-            .for_each(|e_st| {
-                ret.push_str("#NAME#");
-                ret.push_str(&e_st.name);
-                ret.push('\n');
-                ret.push_str("#URL#");
-                ret.push_str(&e_st.url);
-                ret.push('\n');
-            });
+            .for_each(|e_st| e_st.push_formatted(format, &mut ret));
+        ret.push_str(list_footer(format));
         ret
     }
 }
+
+impl EBESMap {
+    /// Like `multi_to_formatted_list()`, but instead of building the whole list in memory,
+    /// every entry is formatted in its own small buffer and written through a buffered `w`,
+    /// sub by sub.
+    pub(crate) async fn multi_write_formatted_list<W>(multi: &[(u8, Option<Self>)], format: ListFormat, w: W) -> io::Result<()>
+        where W: AsyncWrite + Unpin,
+    {
+        let mut w = BufWriter::new(w);
+        w.write_all(list_header(format).as_bytes()).await?;
+        for (sub_idx, e_st_map) in multi.iter().filter_map(|(sub_idx, e_map_opt)| Some((sub_idx, e_map_opt.as_ref()?))) {
+            tracing::debug!("writing list entries of sub {sub_idx}");
+            for e_st in e_st_map.0.values().flatten() {
+                let mut entry = String::new();
+                e_st.push_formatted(format, &mut entry);
+                w.write_all(entry.as_bytes()).await?;
+            }
+        }
+        w.write_all(list_footer(format).as_bytes()).await?;
+        w.flush().await
+    }
+}
+
+fn list_header(format: ListFormat) -> &'static str {
+    match format {
+        ListFormat::Raw => "##Random Text File Format Header##\n",
+        ListFormat::M3u8 => "#EXTM3U\n",
+        ListFormat::Xspf => concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+            "  <trackList>\n",
+        ),
+    }
+}
+
+fn list_footer(format: ListFormat) -> &'static str {
+    match format {
+        ListFormat::Raw | ListFormat::M3u8 => "",
+        ListFormat::Xspf => "  </trackList>\n</playlist>\n",
+    }
+}
+
+fn push_xml_escaped(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+impl ExtractedBooiesExampleStweem {
+    fn push_formatted(&self, format: ListFormat, out: &mut String) {
+        match format {
+            ListFormat::Raw => {
+                out.push_str("#NAME#");
+                out.push_str(&self.name);
+                out.push('\n');
+                out.push_str("#URL#");
+                out.push_str(&self.url);
+                out.push('\n');
+            },
+            ListFormat::M3u8 => {
+                out.push_str("#EXTINF:");
+                match self.duration_secs {
+                    Some(duration_secs) => out.push_str(&duration_secs.to_string()),
+                    None => out.push_str("-1"),
+                }
+                if let Some(group) = &self.group {
+                    // attribute values can't contain double quotes
+                    out.push_str(" group-title=\"");
+                    out.push_str(&group.replace('"', "'"));
+                    out.push('"');
+                }
+                out.push(',');
+                // a new line would end the entry early
+                out.push_str(&self.name.replace('\n', " "));
+                out.push('\n');
+                out.push_str(&self.url);
+                out.push('\n');
+            },
+            ListFormat::Xspf => {
+                out.push_str("    <track>\n      <location>");
+                push_xml_escaped(out, &self.url);
+                out.push_str("</location>\n      <title>");
+                push_xml_escaped(out, &self.name);
+                out.push_str("</title>\n");
+                if let Some(group) = &self.group {
+                    out.push_str("      <album>");
+                    push_xml_escaped(out, group);
+                    out.push_str("</album>\n");
+                }
+                if let Some(duration_secs) = self.duration_secs {
+                    // in milliseconds
+                    out.push_str("      <duration>");
+                    out.push_str(&(duration_secs * 1000).to_string());
+                    out.push_str("</duration>\n");
+                }
+                out.push_str("    </track>\n");
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    fn entry(name: &str, url: &str, duration_secs: Option<u64>, group: Option<&str>) -> ExtractedBooiesExampleStweem {
+        ExtractedBooiesExampleStweem {
+            name: name.into(),
+            url: url.into(),
+            duration_secs,
+            group: group.map(Into::into),
+        }
+    }
+
+    fn multi() -> Vec<(u8, Option<EBESMap>)> {
+        let first = vec![
+            entry("Line\nBreak", "http://h/1?a=1&b=2", Some(90), Some("Say \"Hi\"")),
+            entry("Tom & Jerry's <Show>", "http://h/2", None, None),
+        ];
+        let second = vec![entry("Plain", "http://h/3", Some(5), Some("Group"))];
+        vec![
+            (1, Some(EBESMap(BTreeMap::from([(7, first)])))),
+            (2, None),
+            (3, Some(EBESMap(BTreeMap::from([(1, second)])))),
+        ]
+    }
+
+    #[test]
+    fn xml_escaping() {
+        let mut out = String::from("kept: ");
+        push_xml_escaped(&mut out, r#"a & b < c > d "e" 'f' ü"#);
+        assert_eq!(out, "kept: a &amp; b &lt; c &gt; d &quot;e&quot; &apos;f&apos; ü");
+    }
+
+    #[test]
+    fn m3u8_list() {
+        let expected = concat!(
+            "#EXTM3U\n",
+            "#EXTINF:90 group-title=\"Say 'Hi'\",Line Break\n",
+            "http://h/1?a=1&b=2\n",
+            "#EXTINF:-1,Tom & Jerry's <Show>\n",
+            "http://h/2\n",
+            "#EXTINF:5 group-title=\"Group\",Plain\n",
+            "http://h/3\n",
+        );
+        assert_eq!(EBESMap::multi_to_formatted_list(&multi(), ListFormat::M3u8), expected);
+    }
+
+    #[test]
+    fn xspf_list() {
+        let expected = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+            "  <trackList>\n",
+            "    <track>\n",
+            "      <location>http://h/1?a=1&amp;b=2</location>\n",
+            "      <title>Line\nBreak</title>\n",
+            "      <album>Say &quot;Hi&quot;</album>\n",
+            "      <duration>90000</duration>\n",
+            "    </track>\n",
+            "    <track>\n",
+            "      <location>http://h/2</location>\n",
+            "      <title>Tom &amp; Jerry&apos;s &lt;Show&gt;</title>\n",
+            "    </track>\n",
+            "    <track>\n",
+            "      <location>http://h/3</location>\n",
+            "      <title>Plain</title>\n",
+            "      <album>Group</album>\n",
+            "      <duration>5000</duration>\n",
+            "    </track>\n",
+            "  </trackList>\n",
+            "</playlist>\n",
+        );
+        assert_eq!(EBESMap::multi_to_formatted_list(&multi(), ListFormat::Xspf), expected);
+    }
+
+    #[test]
+    fn streamed_list_matches() {
+        let multi = multi();
+        for format in [ListFormat::Raw, ListFormat::M3u8, ListFormat::Xspf] {
+            let mut streamed = Vec::new();
+            block_on(EBESMap::multi_write_formatted_list(&multi, format, &mut streamed)).unwrap();
+            assert_eq!(String::from_utf8(streamed).unwrap(), EBESMap::multi_to_formatted_list(&multi, format));
+        }
+    }
+}
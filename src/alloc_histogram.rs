@@ -0,0 +1,270 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// small object classes are 8 bytes apart up to 128, then power-of-two classes follow
+const SMALL_CLASS_STEP: usize = 8;
+const SMALL_CLASSES: usize = 16;
+const SMALL_MAX: usize = SMALL_CLASS_STEP * SMALL_CLASSES;
+const SMALL_MAX_LOG2: usize = SMALL_MAX.trailing_zeros() as usize;
+const SIZE_CLASSES: usize = SMALL_CLASSES + usize::BITS as usize - SMALL_MAX_LOG2;
+
+const LOG2_BUCKETS: usize = u64::BITS as usize + 1;
+const MAX_CHAIN_BUCKET: usize = 16;
+
+static ALLOCS: [AtomicU64; SIZE_CLASSES] = [const { AtomicU64::new(0) }; SIZE_CLASSES];
+static ALLOC_BYTES: [AtomicU64; SIZE_CLASSES] = [const { AtomicU64::new(0) }; SIZE_CLASSES];
+static REALLOCS_TO: [AtomicU64; SIZE_CLASSES] = [const { AtomicU64::new(0) }; SIZE_CLASSES];
+static LIFETIMES_NS: [AtomicU64; LOG2_BUCKETS] = [const { AtomicU64::new(0) }; LOG2_BUCKETS];
+static REALLOC_CHAINS: [AtomicU64; MAX_CHAIN_BUCKET + 1] = [const { AtomicU64::new(0) }; MAX_CHAIN_BUCKET + 1];
+static CHAIN_GROWTH: [AtomicU64; LOG2_BUCKETS] = [const { AtomicU64::new(0) }; LOG2_BUCKETS];
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+fn size_class(size: usize) -> usize {
+    match size <= SMALL_MAX {
+        true => size.saturating_sub(1) / SMALL_CLASS_STEP,
+        false => match size.checked_next_power_of_two() {
+            Some(pow2) => SMALL_CLASSES + (pow2.trailing_zeros() as usize - SMALL_MAX_LOG2 - 1),
+            // above the largest power of two
+            None => SIZE_CLASSES - 1,
+        },
+    }
+}
+
+fn size_class_max(class: usize) -> u128 {
+    match class < SMALL_CLASSES {
+        true => ((class + 1) * SMALL_CLASS_STEP) as u128,
+        false => 1u128 << (class - SMALL_CLASSES + SMALL_MAX_LOG2 + 1),
+    }
+}
+
+/// `0` goes to bucket `0`, and `v` goes to bucket `n` where `2^(n-1) <= v < 2^n`.
+fn log2_bucket(v: u64) -> usize {
+    (u64::BITS - v.leading_zeros()) as usize
+}
+
+fn now_ns() -> u64 {
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+fn inc(counter: &AtomicU64, by: u64) {
+    counter.fetch_add(by, Ordering::Relaxed);
+}
+
+/// Stored right before every returned pointer.
+#[repr(C)]
+struct Header {
+    allocated_at_ns: u64,
+    initial_size: u32,
+    reallocs: u32,
+}
+
+const HEADER_SZ: usize = std::mem::size_of::<Header>();
+
+/// Layout of the real allocation, and the offset of the returned pointer in it.
+fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(HEADER_SZ);
+    let size = layout.size().checked_add(align)?;
+    Layout::from_size_align(size, align)
+        .ok()
+        .map(|outer| (outer, align))
+}
+
+/// A system allocator wrapper bucketing allocations by size class, and recording
+/// realloc chains and approximate lifetimes of allocations.
+///
+/// Every allocation carries a small header, so the allocation pattern the system
+/// allocator sees is not exactly the workload's.
+///
+/// Set as the global allocator by the binary when the `alloc_histogram` feature is enabled.
+#[derive(Debug)]
+pub struct HistogramAlloc;
+
+impl HistogramAlloc {
+    /// `ptr` must have been returned by `alloc_with()`, and not freed.
+    unsafe fn header<'a>(ptr: *mut u8) -> &'a mut Header {
+        // SAFETY: the header is right before `ptr`, in the padding `outer_layout()` adds,
+        // which is at least `HEADER_SZ` bytes and aligned to it
+        unsafe { &mut *(ptr.sub(HEADER_SZ) as *mut Header) }
+    }
+
+    /// `f` must return null, or an allocation fitting the `Layout` passed to it.
+    unsafe fn alloc_with(&self, layout: Layout, f: impl FnOnce(Layout) -> *mut u8) -> *mut u8 {
+        let Some((outer, offset)) = outer_layout(layout) else { return std::ptr::null_mut() };
+        let base = f(outer);
+        if base.is_null() {
+            return base;
+        }
+
+        let class = size_class(layout.size());
+        inc(&ALLOCS[class], 1);
+        inc(&ALLOC_BYTES[class], layout.size() as u64);
+
+        // SAFETY: `offset` is within the outer allocation, and `ptr` is only returned after
+        // its header is written
+        unsafe {
+            let ptr = base.add(offset);
+            *Self::header(ptr) = Header {
+                allocated_at_ns: now_ns(),
+                initial_size: u32::try_from(layout.size()).unwrap_or(u32::MAX),
+                reallocs: 0,
+            };
+            ptr
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for HistogramAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: `outer` is never zero-sized, since it includes the header
+        unsafe { self.alloc_with(layout, |outer| System.alloc(outer)) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: as in `alloc()`
+        unsafe { self.alloc_with(layout, |outer| System.alloc_zeroed(outer)) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some((outer, offset)) = outer_layout(layout) else { return };
+        // SAFETY: `ptr` was allocated by us with `layout`, per the `dealloc()` contract
+        let header = unsafe { Self::header(ptr) };
+
+        inc(&LIFETIMES_NS[log2_bucket(now_ns().saturating_sub(header.allocated_at_ns))], 1);
+        inc(&REALLOC_CHAINS[(header.reallocs as usize).min(MAX_CHAIN_BUCKET)], 1);
+        if header.reallocs > 0 {
+            let growth = layout.size() as u64 / (header.initial_size as u64).max(1);
+            inc(&CHAIN_GROWTH[log2_bucket(growth)], 1);
+        }
+
+        // SAFETY: the outer allocation starts `offset` bytes before `ptr`, and was allocated with `outer`
+        unsafe { System.dealloc(ptr.sub(offset), outer) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some((outer, offset)) = outer_layout(layout) else { return std::ptr::null_mut() };
+        let Some(new_outer_size) = new_size.checked_add(offset) else { return std::ptr::null_mut() };
+
+        // SAFETY: as in `dealloc()`, and `new_outer_size` is non-zero
+        let new_base = unsafe { System.realloc(ptr.sub(offset), outer, new_outer_size) };
+        if new_base.is_null() {
+            return new_base;
+        }
+
+        inc(&REALLOCS_TO[size_class(new_size)], 1);
+
+        // SAFETY: realloc() keeps the contents, so the header moved with the rest of the allocation
+        unsafe {
+            let new_ptr = new_base.add(offset);
+            let header = Self::header(new_ptr);
+            header.reallocs = header.reallocs.saturating_add(1);
+            new_ptr
+        }
+    }
+}
+
+fn load(counters: &[AtomicU64]) -> Vec<u64> {
+    counters.iter().map(|c| c.load(Ordering::Relaxed)).collect()
+}
+
+fn fmt_ns(ns: u128) -> String {
+    match ns {
+        0..1_000 => format!("{ns} ns"),
+        1_000..1_000_000 => format!("{} µs", ns / 1_000),
+        1_000_000..1_000_000_000 => format!("{} ms", ns / 1_000_000),
+        _ => format!("{} s", ns / 1_000_000_000),
+    }
+}
+
+/// Print collected histograms to stderr.
+pub fn dump() {
+    let allocs = load(&ALLOCS);
+    let alloc_bytes = load(&ALLOC_BYTES);
+    let reallocs_to = load(&REALLOCS_TO);
+    let total_allocs = allocs.iter().sum::<u64>().max(1);
+
+    eprintln!("allocation size classes:");
+    eprintln!("{:>20} |{:>12}{:>8}{:>16}{:>14}", "size", "allocs", "%", "requested", "reallocs to");
+    for class in 0..SIZE_CLASSES {
+        if allocs[class] == 0 && reallocs_to[class] == 0 {
+            continue;
+        }
+        let lower = match class {
+            0 => 0,
+            _ => size_class_max(class - 1) + 1,
+        };
+        eprintln!("{:>20} |{:>12}{:>8.2}{:>16}{:>14}",
+            format!("{lower}..={}", size_class_max(class)),
+            allocs[class],
+            allocs[class] as f64 * 100.0 / total_allocs as f64,
+            alloc_bytes[class],
+            reallocs_to[class]);
+    }
+
+    let lifetimes = load(&LIFETIMES_NS);
+    let total_freed = lifetimes.iter().sum::<u64>().max(1);
+    eprintln!("\nlifetimes of freed allocations:");
+    eprintln!("{:>16} |{:>12}{:>8}", "lifetime", "count", "%");
+    for (bucket, count) in lifetimes.iter().copied().enumerate().filter(|&(_, c)| c > 0) {
+        eprintln!("{:>16} |{count:>12}{:>8.2}",
+            format!("< {}", fmt_ns(1u128 << bucket)),
+            count as f64 * 100.0 / total_freed as f64);
+    }
+
+    let chains = load(&REALLOC_CHAINS);
+    eprintln!("\nrealloc chain lengths of freed allocations:");
+    eprintln!("{:>16} |{:>12}{:>8}", "reallocs", "count", "%");
+    for (len, count) in chains.iter().copied().enumerate().filter(|&(_, c)| c > 0) {
+        let len_str = match len {
+            MAX_CHAIN_BUCKET => format!("{MAX_CHAIN_BUCKET}+"),
+            _ => len.to_string(),
+        };
+        eprintln!("{len_str:>16} |{count:>12}{:>8.2}", count as f64 * 100.0 / total_freed as f64);
+    }
+
+    let growth = load(&CHAIN_GROWTH);
+    eprintln!("\nfinal/initial size growth of realloc chains:");
+    eprintln!("{:>16} |{:>12}", "growth", "count");
+    for (bucket, count) in growth.iter().copied().enumerate().filter(|&(_, c)| c > 0) {
+        eprintln!("{:>16} |{count:>12}", format!("< {}x", 1u128 << bucket));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_class_bounds() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(SMALL_MAX), SMALL_CLASSES - 1);
+        assert_eq!(size_class(SMALL_MAX + 1), SMALL_CLASSES);
+        assert_eq!(size_class(1 << (usize::BITS - 1)), SIZE_CLASSES - 2);
+        assert_eq!(size_class((1 << (usize::BITS - 1)) + 1), SIZE_CLASSES - 1);
+        assert_eq!(size_class(usize::MAX), SIZE_CLASSES - 1);
+        for size in [1, 8, 9, SMALL_MAX, SMALL_MAX + 1, 4096, 4097, usize::MAX] {
+            assert!(size as u128 <= size_class_max(size_class(size)), "size {size}");
+        }
+    }
+}
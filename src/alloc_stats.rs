@@ -0,0 +1,139 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde::Serialize;
+use stats_alloc::{Region, INSTRUMENTED_SYSTEM};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// `stats_alloc`'s instrumented system allocator, with live/peak bytes tracking on top.
+///
+/// Set as the global allocator by the binary when the `stats_alloc` feature is enabled.
+#[derive(Debug)]
+pub struct PeakTrackingAlloc;
+
+impl PeakTrackingAlloc {
+    fn grow(by: usize) {
+        let live = LIVE_BYTES.fetch_add(by, Ordering::Relaxed) + by;
+        PEAK_LIVE_BYTES.fetch_max(live, Ordering::Relaxed);
+    }
+
+    fn shrink(by: usize) {
+        LIVE_BYTES.fetch_sub(by, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: all calls are forwarded to `INSTRUMENTED_SYSTEM` unchanged, under the same contracts
+unsafe impl GlobalAlloc for PeakTrackingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { INSTRUMENTED_SYSTEM.alloc(layout) };
+        if !ptr.is_null() {
+            Self::grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        Self::shrink(layout.size());
+        unsafe { INSTRUMENTED_SYSTEM.dealloc(ptr, layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { INSTRUMENTED_SYSTEM.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            Self::grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { INSTRUMENTED_SYSTEM.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            match new_size >= layout.size() {
+                true => Self::grow(new_size - layout.size()),
+                false => Self::shrink(layout.size() - new_size),
+            }
+        }
+        new_ptr
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub(crate) struct AllocStats {
+    pub(crate) allocations: usize,
+    pub(crate) deallocations: usize,
+    pub(crate) reallocations: usize,
+    pub(crate) bytes_allocated: usize,
+    pub(crate) bytes_deallocated: usize,
+    pub(crate) bytes_reallocated: isize,
+    /// peak live bytes reached, relative to live bytes at the start of measurement
+    pub(crate) peak_live_bytes: usize,
+}
+
+impl AllocStats {
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.allocations += other.allocations;
+        self.deallocations += other.deallocations;
+        self.reallocations += other.reallocations;
+        self.bytes_allocated += other.bytes_allocated;
+        self.bytes_deallocated += other.bytes_deallocated;
+        self.bytes_reallocated += other.bytes_reallocated;
+        self.peak_live_bytes = self.peak_live_bytes.max(other.peak_live_bytes);
+    }
+}
+
+/// Measures allocation activity between `start()` and `finish()`.
+///
+/// Counters are process-global. So measurements include allocations done by
+/// anything running concurrently, and are only exact with no concurrency.
+///
+/// Measurements can be nested, as long as they are finished in reverse order.
+pub(crate) struct AllocMeasurement {
+    region: Region<'static, System>,
+    live_at_start: usize,
+    prev_peak: usize,
+}
+
+impl AllocMeasurement {
+    pub(crate) fn start() -> Self {
+        let live_at_start = LIVE_BYTES.load(Ordering::Relaxed);
+        let prev_peak = PEAK_LIVE_BYTES.swap(live_at_start, Ordering::Relaxed);
+        let region = Region::new(&INSTRUMENTED_SYSTEM);
+        Self { region, live_at_start, prev_peak }
+    }
+
+    pub(crate) fn finish(self) -> AllocStats {
+        let change = self.region.change();
+        // restore peak for enclosing measurements
+        let peak = PEAK_LIVE_BYTES.fetch_max(self.prev_peak, Ordering::Relaxed);
+        AllocStats {
+            allocations: change.allocations,
+            deallocations: change.deallocations,
+            reallocations: change.reallocations,
+            bytes_allocated: change.bytes_allocated,
+            bytes_deallocated: change.bytes_deallocated,
+            bytes_reallocated: change.bytes_reallocated,
+            peak_live_bytes: peak.saturating_sub(self.live_at_start),
+        }
+    }
+}
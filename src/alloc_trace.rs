@@ -0,0 +1,644 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Allocation trace format, recording (with the `alloc_trace` feature) and replaying.
+//!
+//! A trace file is `MAGIC`, a little-endian `u32` version, then fixed-size
+//! little-endian records in global order:
+//!
+//! | bytes | field                                         |
+//! |-------|-----------------------------------------------|
+//! | 1     | op kind                                       |
+//! | 1     | log2 of alignment                             |
+//! | 2     | (padding)                                     |
+//! | 4     | thread id                                     |
+//! | 8     | timestamp (ns since recording started)        |
+//! | 8     | pointer (old pointer for realloc)             |
+//! | 8     | size (new size for realloc)                   |
+//! | 8     | new pointer (realloc only)                    |
+
+use thiserror::Error;
+
+use std::alloc::Layout;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Barrier;
+use std::time::{Duration, Instant};
+
+use crate::fs_util::file::ExistentReadableFile;
+
+use crate::AllocPerfRes;
+
+const MAGIC: &[u8; 8] = b"APTTRACE";
+const VERSION: u32 = 1;
+const HEADER_SZ: usize = MAGIC.len() + 4;
+const RECORD_SZ: usize = 40;
+
+#[derive(Debug, Error)]
+pub enum AllocTraceError {
+    #[error("creating trace file @ '{}' failed: {source}", path.to_string_lossy())]
+    CreateFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("writing to trace file failed: {0}")]
+    WriteFailed(#[source] std::io::Error),
+    #[error("recording already started")]
+    AlreadyRecording,
+    #[error("not an allocation trace file (bad magic bytes)")]
+    BadMagic,
+    #[error("unsupported trace version {found} (supported: {VERSION})")]
+    UnsupportedVersion { found: u32 },
+    #[error("trace is truncated ({0} trailing bytes)")]
+    Truncated(usize),
+    #[error("invalid op kind {kind} in record {idx}")]
+    InvalidOpKind { kind: u8, idx: usize },
+    #[error("invalid layout (size={size}, align=2^{align_log2}) in record {idx}")]
+    InvalidLayout { size: u64, align_log2: u8, idx: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+enum OpKind {
+    Alloc = 0,
+    AllocZeroed = 1,
+    Dealloc = 2,
+    Realloc = 3,
+}
+
+impl OpKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Alloc),
+            1 => Some(Self::AllocZeroed),
+            2 => Some(Self::Dealloc),
+            3 => Some(Self::Realloc),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Record {
+    kind: OpKind,
+    align_log2: u8,
+    thread: u32,
+    ts_ns: u64,
+    ptr: u64,
+    size: u64,
+    new_ptr: u64,
+}
+
+impl Record {
+    #[cfg(any(feature = "alloc_trace", test))]
+    fn to_bytes(self) -> [u8; RECORD_SZ] {
+        let mut bytes = [0u8; RECORD_SZ];
+        bytes[0] = self.kind as u8;
+        bytes[1] = self.align_log2;
+        bytes[4..8].copy_from_slice(&self.thread.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.ts_ns.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.ptr.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.size.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.new_ptr.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SZ], idx: usize) -> Result<Self, AllocTraceError> {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i+4].try_into().expect("impossible"));
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i+8].try_into().expect("impossible"));
+        let kind = OpKind::from_u8(bytes[0])
+            .ok_or(AllocTraceError::InvalidOpKind { kind: bytes[0], idx })?;
+        Ok(Self {
+            kind,
+            align_log2: bytes[1],
+            thread: u32_at(4),
+            ts_ns: u64_at(8),
+            ptr: u64_at(16),
+            size: u64_at(24),
+            new_ptr: u64_at(32),
+        })
+    }
+
+    fn invalid_layout(&self, idx: usize) -> AllocTraceError {
+        AllocTraceError::InvalidLayout { size: self.size, align_log2: self.align_log2, idx }
+    }
+
+    fn layout(&self, idx: usize) -> Result<Layout, AllocTraceError> {
+        let align = 1usize.checked_shl(self.align_log2.into()).ok_or_else(|| self.invalid_layout(idx))?;
+        self.layout_with_align(align, idx)
+    }
+
+    /// Zero sizes are invalid too, since allocating them with `std::alloc` in replay is UB.
+    fn layout_with_align(&self, align: usize, idx: usize) -> Result<Layout, AllocTraceError> {
+        let size = usize::try_from(self.size).map_err(|_| self.invalid_layout(idx))?;
+        if size == 0 {
+            Err(self.invalid_layout(idx))?;
+        }
+        Layout::from_size_align(size, align).map_err(|_| self.invalid_layout(idx))
+    }
+}
+
+#[cfg(feature = "alloc_trace")]
+pub use record::{start_recording, stop_recording, TraceAlloc};
+
+#[cfg(feature = "alloc_trace")]
+mod record {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::{Cell, UnsafeCell};
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    use super::{AllocTraceError, OpKind, Record, MAGIC, RECORD_SZ, VERSION};
+
+    const BUF_RECORDS: usize = 4096;
+
+    struct TraceBuf {
+        bytes: [u8; RECORD_SZ * BUF_RECORDS],
+        len: usize,
+    }
+
+    struct Locked(UnsafeCell<TraceBuf>);
+
+    // SAFETY: only accessed while holding LOCK
+    unsafe impl Sync for Locked {}
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+    static LOCK: AtomicBool = AtomicBool::new(false);
+    static BUF: Locked = Locked(UnsafeCell::new(TraceBuf { bytes: [0; RECORD_SZ * BUF_RECORDS], len: 0 }));
+    static FILE: OnceLock<File> = OnceLock::new();
+    static START: OnceLock<Instant> = OnceLock::new();
+    static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+
+    thread_local! {
+        // const-initialized with no destructor, so no allocations happen on access
+        static THREAD_ID: Cell<u32> = const { Cell::new(0) };
+    }
+
+    fn thread_id() -> u32 {
+        THREAD_ID.try_with(|id| match id.get() {
+            0 => {
+                let new_id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+                id.set(new_id);
+                new_id
+            },
+            existing => existing,
+        }).unwrap_or(u32::MAX)
+    }
+
+    struct LockGuard;
+
+    impl LockGuard {
+        fn lock() -> Self {
+            let mut spins = 0u32;
+            while LOCK.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+                spins += 1;
+                match spins < 64 {
+                    true => std::hint::spin_loop(),
+                    false => std::thread::yield_now(),
+                }
+            }
+            Self
+        }
+
+        fn buf(&mut self) -> &mut TraceBuf {
+            // SAFETY: exclusive access is guaranteed while the guard is alive
+            unsafe { &mut *BUF.0.get() }
+        }
+    }
+
+    impl Drop for LockGuard {
+        fn drop(&mut self) {
+            LOCK.store(false, Ordering::Release);
+        }
+    }
+
+    fn flush(buf: &mut TraceBuf) -> std::io::Result<()> {
+        if let Some(mut file) = FILE.get() {
+            file.write_all(&buf.bytes[..buf.len * RECORD_SZ])?;
+        }
+        buf.len = 0;
+        Ok(())
+    }
+
+    fn push(guard: &mut LockGuard, kind: OpKind, layout: Layout, ptr: *mut u8, size: usize, new_ptr: *mut u8) {
+        let record = Record {
+            kind,
+            align_log2: layout.align().trailing_zeros() as u8,
+            thread: thread_id(),
+            ts_ns: START.get().map(|s| s.elapsed().as_nanos() as u64).unwrap_or_default(),
+            ptr: ptr as u64,
+            size: size as u64,
+            new_ptr: new_ptr as u64,
+        };
+        let buf = guard.buf();
+        buf.bytes[buf.len * RECORD_SZ..][..RECORD_SZ].copy_from_slice(&record.to_bytes());
+        buf.len += 1;
+        if buf.len == BUF_RECORDS && flush(buf).is_err() {
+            // can't report errors from here, stop recording instead of writing a trace with gaps
+            ENABLED.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Start recording allocator ops into a new trace file at `path`.
+    ///
+    /// Can only be called once per process.
+    pub fn start_recording(path: impl AsRef<Path>) -> Result<(), AllocTraceError> {
+        let path = path.as_ref();
+        let mut file = File::create(path)
+            .map_err(|source| AllocTraceError::CreateFailed { path: path.to_owned(), source })?;
+        file.write_all(MAGIC)
+            .and_then(|_| file.write_all(&VERSION.to_le_bytes()))
+            .map_err(AllocTraceError::WriteFailed)?;
+
+        let _guard = LockGuard::lock();
+        FILE.set(file).map_err(|_| AllocTraceError::AlreadyRecording)?;
+        let _ = START.set(Instant::now());
+        ENABLED.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stop recording, and flush buffered records to the trace file.
+    pub fn stop_recording() -> Result<(), AllocTraceError> {
+        let mut guard = LockGuard::lock();
+        ENABLED.store(false, Ordering::Relaxed);
+        flush(guard.buf()).map_err(AllocTraceError::WriteFailed)?;
+        if let Some(file) = FILE.get() {
+            file.sync_all().map_err(AllocTraceError::WriteFailed)?;
+        }
+        Ok(())
+    }
+
+    /// A system allocator wrapper recording every op when recording is started.
+    ///
+    /// While recording, ops are serialized with a global lock, so that the
+    /// recorded order is the order ops actually took effect in.
+    ///
+    /// Set as the global allocator by the binary when the `alloc_trace` feature is enabled.
+    #[derive(Debug)]
+    pub struct TraceAlloc;
+
+    // SAFETY: all calls are forwarded to `System` unchanged, under the same contracts
+    unsafe impl GlobalAlloc for TraceAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if !ENABLED.load(Ordering::Relaxed) {
+                return unsafe { System.alloc(layout) };
+            }
+            let mut guard = LockGuard::lock();
+            let ptr = unsafe { System.alloc(layout) };
+            if !ptr.is_null() && ENABLED.load(Ordering::Relaxed) {
+                push(&mut guard, OpKind::Alloc, layout, ptr, layout.size(), std::ptr::null_mut());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            if !ENABLED.load(Ordering::Relaxed) {
+                return unsafe { System.alloc_zeroed(layout) };
+            }
+            let mut guard = LockGuard::lock();
+            let ptr = unsafe { System.alloc_zeroed(layout) };
+            if !ptr.is_null() && ENABLED.load(Ordering::Relaxed) {
+                push(&mut guard, OpKind::AllocZeroed, layout, ptr, layout.size(), std::ptr::null_mut());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if !ENABLED.load(Ordering::Relaxed) {
+                return unsafe { System.dealloc(ptr, layout) };
+            }
+            let mut guard = LockGuard::lock();
+            if ENABLED.load(Ordering::Relaxed) {
+                push(&mut guard, OpKind::Dealloc, layout, ptr, layout.size(), std::ptr::null_mut());
+            }
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            if !ENABLED.load(Ordering::Relaxed) {
+                return unsafe { System.realloc(ptr, layout, new_size) };
+            }
+            let mut guard = LockGuard::lock();
+            let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+            if !new_ptr.is_null() && ENABLED.load(Ordering::Relaxed) {
+                push(&mut guard, OpKind::Realloc, layout, ptr, new_size, new_ptr);
+            }
+            new_ptr
+        }
+    }
+}
+
+/// A replay op, with pointers replaced by object indices.
+#[derive(Clone, Copy, Debug)]
+enum ReplayOp {
+    Alloc { obj: usize, zeroed: bool },
+    Dealloc { obj: usize },
+    Realloc { obj: usize, new_obj: usize },
+}
+
+struct ReplayPlan {
+    // per recorded thread, in recorded order
+    threads: Vec<Vec<ReplayOp>>,
+    layouts: Vec<Layout>,
+    // objects still live at the end of the trace
+    leftover: Vec<usize>,
+    ops: usize,
+    skipped: usize,
+    recorded_duration: Duration,
+}
+
+impl ReplayPlan {
+    fn from_trace(trace: &[u8]) -> Result<Self, AllocTraceError> {
+        let (header, records) = match trace.len() >= HEADER_SZ {
+            true => trace.split_at(HEADER_SZ),
+            false => Err(AllocTraceError::BadMagic)?,
+        };
+        if &header[..MAGIC.len()] != MAGIC {
+            Err(AllocTraceError::BadMagic)?;
+        }
+        let version = u32::from_le_bytes(header[MAGIC.len()..].try_into().expect("impossible"));
+        if version != VERSION {
+            Err(AllocTraceError::UnsupportedVersion { found: version })?;
+        }
+
+        let chunks = records.chunks_exact(RECORD_SZ);
+        if !chunks.remainder().is_empty() {
+            tracing::warn!("ignoring incomplete trailing record");
+        }
+
+        let mut thread_idx_map = HashMap::new();
+        let mut threads = Vec::<Vec<ReplayOp>>::new();
+        let mut layouts = Vec::new();
+        // recorded pointer => object index
+        let mut live = HashMap::<u64, usize>::new();
+        let (mut ops, mut skipped, mut last_ts) = (0, 0, 0);
+
+        for (idx, chunk) in chunks.enumerate() {
+            let record = Record::from_bytes(chunk.try_into().expect("impossible"), idx)?;
+            last_ts = record.ts_ns;
+
+            let op = match record.kind {
+                OpKind::Alloc | OpKind::AllocZeroed => {
+                    let obj = layouts.len();
+                    layouts.push(record.layout(idx)?);
+                    let _ = live.insert(record.ptr, obj);
+                    ReplayOp::Alloc { obj, zeroed: record.kind == OpKind::AllocZeroed }
+                },
+                OpKind::Dealloc => match live.remove(&record.ptr) {
+                    Some(obj) => ReplayOp::Dealloc { obj },
+                    // allocated before recording started
+                    None => { skipped += 1; continue; },
+                },
+                OpKind::Realloc => match live.remove(&record.ptr) {
+                    Some(obj) => {
+                        let new_obj = layouts.len();
+                        let new_layout = record.layout_with_align(layouts[obj].align(), idx)?;
+                        layouts.push(new_layout);
+                        let _ = live.insert(record.new_ptr, new_obj);
+                        ReplayOp::Realloc { obj, new_obj }
+                    },
+                    None => { skipped += 1; continue; },
+                },
+            };
+
+            let thread_idx = *thread_idx_map.entry(record.thread).or_insert_with(|| {
+                threads.push(Vec::new());
+                threads.len() - 1
+            });
+            threads[thread_idx].push(op);
+            ops += 1;
+        }
+
+        let leftover = live.into_values().collect();
+        let recorded_duration = Duration::from_nanos(last_ts);
+        Ok(Self { threads, layouts, leftover, ops, skipped, recorded_duration })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ReplayReport {
+    pub(crate) threads: usize,
+    pub(crate) ops: usize,
+    pub(crate) skipped: usize,
+    pub(crate) recorded_duration: Duration,
+    pub(crate) replay_duration: Duration,
+}
+
+impl ReplayReport {
+    pub(crate) fn print(&self) {
+        let Self { threads, ops, skipped, recorded_duration, replay_duration } = self;
+        println!("replayed {ops} allocator ops in {threads} threads ({skipped} ops on pre-recording allocations skipped)");
+        println!("  recorded run: {:.3} s", recorded_duration.as_secs_f64());
+        println!("  replay:       {:.3} s ({:.0} ops/s)",
+            replay_duration.as_secs_f64(),
+            *ops as f64 / replay_duration.as_secs_f64().max(f64::MIN_POSITIVE));
+    }
+}
+
+fn wait_for(slot: &AtomicPtr<u8>) -> *mut u8 {
+    let mut spins = 0u32;
+    loop {
+        let ptr = slot.swap(std::ptr::null_mut(), Ordering::Acquire);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        spins += 1;
+        match spins < 64 {
+            true => std::hint::spin_loop(),
+            false => std::thread::yield_now(),
+        }
+    }
+}
+
+fn replay_thread(ops: &[ReplayOp], layouts: &[Layout], slots: &[AtomicPtr<u8>], touch: bool) {
+    for op in ops {
+        // SAFETY: layouts are validated when the plan is built, and objects are
+        // only freed/reallocated once, with the layout they were allocated with.
+        unsafe {
+            match *op {
+                ReplayOp::Alloc { obj, zeroed } => {
+                    let layout = layouts[obj];
+                    let ptr = match zeroed {
+                        true => std::alloc::alloc_zeroed(layout),
+                        false => std::alloc::alloc(layout),
+                    };
+                    if ptr.is_null() {
+                        std::alloc::handle_alloc_error(layout);
+                    }
+                    if touch && !zeroed {
+                        ptr.write_bytes(0xa5, layout.size());
+                    }
+                    slots[obj].store(ptr, Ordering::Release);
+                },
+                ReplayOp::Dealloc { obj } => {
+                    // may wait for the allocating thread to catch up
+                    let ptr = wait_for(&slots[obj]);
+                    std::alloc::dealloc(ptr, layouts[obj]);
+                },
+                ReplayOp::Realloc { obj, new_obj } => {
+                    let ptr = wait_for(&slots[obj]);
+                    let (old_layout, new_layout) = (layouts[obj], layouts[new_obj]);
+                    let new_ptr = std::alloc::realloc(ptr, old_layout, new_layout.size());
+                    if new_ptr.is_null() {
+                        std::alloc::handle_alloc_error(new_layout);
+                    }
+                    if touch && new_layout.size() > old_layout.size() {
+                        new_ptr.add(old_layout.size()).write_bytes(0xa5, new_layout.size() - old_layout.size());
+                    }
+                    slots[new_obj].store(new_ptr, Ordering::Release);
+                },
+            }
+        }
+    }
+}
+
+/// Replay a recorded trace against the global allocator, one thread per recorded thread.
+///
+/// Each thread replays its own ops in recorded order. Ops on objects allocated by
+/// another thread wait until that allocation is replayed.
+pub(crate) async fn replay(path: impl AsRef<Path>, touch: bool) -> AllocPerfRes<ReplayReport> {
+    let trace = ExistentReadableFile::open(path).await?.read().await?;
+
+    blocking::unblock(move || {
+        let plan = ReplayPlan::from_trace(&trace)?;
+        drop(trace);
+        tracing::info!("replaying {} ops in {} threads", plan.ops, plan.threads.len());
+
+        let slots = {0..plan.layouts.len()}
+            .map(|_| AtomicPtr::new(std::ptr::null_mut()))
+            .collect::<Vec<_>>();
+        let barrier = Barrier::new(plan.threads.len() + 1);
+
+        let replay_duration = std::thread::scope(|s| {
+            for thread_ops in &plan.threads {
+                let (layouts, slots, barrier) = (&plan.layouts, &slots, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    replay_thread(thread_ops, layouts, slots, touch);
+                });
+            }
+            let _ = barrier.wait();
+            // all threads are waited for at scope end
+            Instant::now()
+        }).elapsed();
+
+        for obj in &plan.leftover {
+            let ptr = slots[*obj].swap(std::ptr::null_mut(), Ordering::Relaxed);
+            if !ptr.is_null() {
+                // SAFETY: allocated in replay with this layout, and not freed
+                unsafe { std::alloc::dealloc(ptr, plan.layouts[*obj]) };
+            }
+        }
+
+        Ok(ReplayReport {
+            threads: plan.threads.len(),
+            ops: plan.ops,
+            skipped: plan.skipped,
+            recorded_duration: plan.recorded_duration,
+            replay_duration,
+        })
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(kind: OpKind, thread: u32, ptr: u64, size: u64, new_ptr: u64) -> Record {
+        Record { kind, align_log2: 3, thread, ts_ns: ptr, ptr, size, new_ptr }
+    }
+
+    fn encode(records: &[Record]) -> Vec<u8> {
+        let mut trace = MAGIC.to_vec();
+        trace.extend_from_slice(&VERSION.to_le_bytes());
+        for record in records {
+            trace.extend_from_slice(&record.to_bytes());
+        }
+        trace
+    }
+
+    #[test]
+    fn round_trip() {
+        let records = [
+            record(OpKind::Alloc, 1, 0x1000, 16, 0),
+            // on pointers allocated before recording started
+            record(OpKind::Dealloc, 1, 0x9000, 64, 0),
+            record(OpKind::Realloc, 2, 0x8000, 128, 0xa000),
+            record(OpKind::Realloc, 2, 0x1000, 32, 0x2000),
+            record(OpKind::AllocZeroed, 1, 0x3000, 8, 0),
+            record(OpKind::Dealloc, 2, 0x2000, 32, 0),
+        ];
+        let trace = encode(&records);
+        assert_eq!(trace.len(), HEADER_SZ + records.len() * RECORD_SZ);
+
+        let chunks = trace[HEADER_SZ..].chunks_exact(RECORD_SZ);
+        for (idx, (chunk, record)) in chunks.zip(&records).enumerate() {
+            assert_eq!(Record::from_bytes(chunk.try_into().unwrap(), idx).unwrap(), *record);
+        }
+
+        let plan = ReplayPlan::from_trace(&trace).unwrap();
+        assert_eq!(plan.ops, 4);
+        assert_eq!(plan.skipped, 2);
+        assert_eq!(plan.threads.len(), 2);
+        assert!(matches!(
+            plan.threads[0][..],
+            [ReplayOp::Alloc { obj: 0, zeroed: false }, ReplayOp::Alloc { obj: 2, zeroed: true }]
+        ));
+        assert!(matches!(
+            plan.threads[1][..],
+            [ReplayOp::Realloc { obj: 0, new_obj: 1 }, ReplayOp::Dealloc { obj: 1 }]
+        ));
+        let sizes = plan.layouts.iter().map(Layout::size).collect::<Vec<_>>();
+        assert_eq!(sizes, [16, 32, 8]);
+        assert!(plan.layouts.iter().all(|layout| layout.align() == 8));
+        assert_eq!(plan.leftover, [2]);
+        assert_eq!(plan.recorded_duration, Duration::from_nanos(0x2000));
+    }
+
+    #[test]
+    fn bad_header_rejected() {
+        let mut trace = encode(&[]);
+        trace[0] = b'X';
+        assert!(matches!(ReplayPlan::from_trace(&trace), Err(AllocTraceError::BadMagic)));
+
+        let mut trace = encode(&[]);
+        trace[MAGIC.len()] = 2;
+        assert!(matches!(
+            ReplayPlan::from_trace(&trace),
+            Err(AllocTraceError::UnsupportedVersion { found: 2 })
+        ));
+    }
+
+    #[test]
+    fn zero_sizes_rejected() {
+        let zero_alloc = encode(&[record(OpKind::Alloc, 1, 0x1000, 0, 0)]);
+        assert!(matches!(ReplayPlan::from_trace(&zero_alloc), Err(AllocTraceError::InvalidLayout { size: 0, idx: 0, .. })));
+
+        let zero_realloc = encode(&[
+            record(OpKind::Alloc, 1, 0x1000, 16, 0),
+            record(OpKind::Realloc, 1, 0x1000, 0, 0x2000),
+        ]);
+        assert!(matches!(ReplayPlan::from_trace(&zero_realloc), Err(AllocTraceError::InvalidLayout { size: 0, idx: 1, .. })));
+    }
+}
@@ -0,0 +1,236 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde::Serialize;
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::AllocPerfRes;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct CpuTimes {
+    user: Duration,
+    system: Duration,
+}
+
+impl CpuTimes {
+    #[cfg(unix)]
+    fn now() -> Self {
+        fn tv_to_dur(tv: libc::timeval) -> Duration {
+            Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+        }
+
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        // SAFETY: getrusage() only writes to the passed struct, and RUSAGE_SELF is always valid
+        let ret = unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) };
+        if ret != 0 {
+            tracing::warn!("getrusage() failed, user/system times will be reported as zeros");
+            return Self::default();
+        }
+        // SAFETY: initialized by a successful getrusage() call
+        let usage = unsafe { usage.assume_init() };
+        Self {
+            user: tv_to_dur(usage.ru_utime),
+            system: tv_to_dur(usage.ru_stime),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn now() -> Self {
+        Self::default()
+    }
+
+    fn since(self, earlier: Self) -> Self {
+        Self {
+            user: self.user.saturating_sub(earlier.user),
+            system: self.system.saturating_sub(earlier.system),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    wall: Duration,
+    cpu: CpuTimes,
+}
+
+/// Summary statistics of a series of measurements, in seconds.
+#[derive(Debug, Serialize)]
+pub(crate) struct Stats {
+    pub(crate) mean: f64,
+    pub(crate) median: f64,
+    pub(crate) stddev: f64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    /// number of values outside the `[Q1 - 1.5*IQR, Q3 + 1.5*IQR]` fences
+    pub(crate) outliers: usize,
+}
+
+impl Stats {
+    fn from_secs(values: &[f64]) -> Self {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        let n = sorted.len();
+        if n == 0 {
+            return Self { mean: 0.0, median: 0.0, stddev: 0.0, min: 0.0, max: 0.0, outliers: 0 };
+        }
+
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let stddev = match n {
+            1 => 0.0,
+            _ => (sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt(),
+        };
+
+        let quantile = |q: f64| {
+            let pos = q * (n - 1) as f64;
+            let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+            sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+        };
+
+        let (q1, median, q3) = (quantile(0.25), quantile(0.5), quantile(0.75));
+        let iqr = q3 - q1;
+        let (lower_fence, upper_fence) = (q1 - 1.5 * iqr, q3 + 1.5 * iqr);
+        let outliers = sorted.iter().filter(|&&v| v < lower_fence || v > upper_fence).count();
+
+        Self { mean, median, stddev, min: sorted[0], max: sorted[n - 1], outliers }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BenchReport {
+    pub(crate) name: String,
+    pub(crate) runs: usize,
+    pub(crate) warmup: usize,
+    pub(crate) wall: Stats,
+    pub(crate) user: Stats,
+    pub(crate) system: Stats,
+    /// raw wall times of every measured run, in seconds
+    pub(crate) wall_times: Vec<f64>,
+}
+
+impl BenchReport {
+    fn new(name: String, warmup: usize, samples: &[Sample]) -> Self {
+        let secs = |f: fn(&Sample) -> Duration| samples.iter().map(f).map(|d| d.as_secs_f64()).collect::<Vec<_>>();
+        let wall_times = secs(|s| s.wall);
+        Self {
+            name,
+            runs: samples.len(),
+            warmup,
+            wall: Stats::from_secs(&wall_times),
+            user: Stats::from_secs(&secs(|s| s.cpu.user)),
+            system: Stats::from_secs(&secs(|s| s.cpu.system)),
+            wall_times,
+        }
+    }
+
+    pub(crate) fn print_human(&self) {
+        let Self { name, runs, warmup, wall, user, system, .. } = self;
+        println!("Benchmark: {name}");
+        println!("  Time (mean ± σ):    {:8.3} s ± {:6.3} s    [User: {:.3} s, System: {:.3} s]",
+            wall.mean, wall.stddev, user.mean, system.mean);
+        println!("  Time (median):      {:8.3} s                [User: {:.3} s, System: {:.3} s]",
+            wall.median, user.median, system.median);
+        println!("  Range (min … max):  {:8.3} s … {:6.3} s    {runs} runs ({warmup} warmup)",
+            wall.min, wall.max);
+        if wall.outliers > 0 {
+            println!("\n  Warning: {} statistical outlier(s) detected in wall times.", wall.outliers);
+        }
+    }
+
+    pub(crate) fn print_json(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => println!("{json}"),
+            // only possible with non-string map keys, which we don't have
+            Err(e) => tracing::error!("failed to serialize bench report to JSON: {e}"),
+        }
+    }
+}
+
+/// Run `f` `warmup` times discarding results, then `runs` times measuring
+/// wall, user and system time of each run.
+pub(crate) async fn bench<F, Fu, T>(name: String, runs: usize, warmup: usize, mut f: F) -> AllocPerfRes<BenchReport>
+    where F: FnMut() -> Fu,
+          Fu: Future<Output = AllocPerfRes<T>>,
+{
+    for w in 1..=warmup {
+        tracing::info!("warmup run {w}/{warmup}");
+        let _ = f().await?;
+    }
+
+    let mut samples = Vec::with_capacity(runs);
+    for r in 1..=runs {
+        tracing::info!("bench run {r}/{runs}");
+        let cpu_start = CpuTimes::now();
+        let wall_start = Instant::now();
+        let ret = f().await?;
+        // include dropping run results in measurements
+        drop(ret);
+        let wall = wall_start.elapsed();
+        let cpu = CpuTimes::now().since(cpu_start);
+        tracing::info!("bench run {r}/{runs} took {:.3} s", wall.as_secs_f64());
+        samples.push(Sample { wall, cpu });
+    }
+
+    Ok(BenchReport::new(name, warmup, &samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(found: f64, expected: f64) {
+        assert!((found - expected).abs() < 1e-9, "found {found}, expected {expected}");
+    }
+
+    #[test]
+    fn stats_empty() {
+        let stats = Stats::from_secs(&[]);
+        assert_eq!((stats.mean, stats.median, stats.stddev, stats.min, stats.max), (0.0, 0.0, 0.0, 0.0, 0.0));
+        assert_eq!(stats.outliers, 0);
+    }
+
+    #[test]
+    fn stats_single_sample() {
+        let stats = Stats::from_secs(&[1.5]);
+        assert_eq!((stats.mean, stats.median, stats.stddev, stats.min, stats.max), (1.5, 1.5, 0.0, 1.5, 1.5));
+        assert_eq!(stats.outliers, 0);
+    }
+
+    #[test]
+    fn stats_quantiles() {
+        // unsorted on purpose, even count so the median is interpolated
+        let stats = Stats::from_secs(&[4.0, 1.0, 3.0, 2.0]);
+        assert_close(stats.mean, 2.5);
+        assert_close(stats.median, 2.5);
+        assert_close(stats.stddev, (5.0f64 / 3.0).sqrt());
+        assert_eq!((stats.min, stats.max), (1.0, 4.0));
+        assert_eq!(stats.outliers, 0);
+    }
+
+    #[test]
+    fn stats_outliers() {
+        // Q1 = 2.5, Q3 = 4.5, so the fences are at -0.5 and 7.5
+        let stats = Stats::from_secs(&[1.0, 2.0, 3.0, 3.0, 4.0, 5.0, 100.0]);
+        assert_close(stats.median, 3.0);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(stats.outliers, 1);
+    }
+}
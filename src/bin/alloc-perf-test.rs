@@ -22,13 +22,21 @@ use tracing_subscriber::EnvFilter;
 use std::io;
 
 #[cfg(feature = "stats_alloc")]
-use stats_alloc::{StatsAlloc, INSTRUMENTED_SYSTEM};
+use stats_alloc::INSTRUMENTED_SYSTEM;
 #[cfg(feature = "stats_alloc")]
-use std::alloc::System;
+use alloc_perf_test::alloc_stats::PeakTrackingAlloc;
 
 #[cfg(feature = "stats_alloc")]
 #[global_allocator]
-static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+static GLOBAL: PeakTrackingAlloc = PeakTrackingAlloc;
+
+#[cfg(feature = "alloc_histogram")]
+#[global_allocator]
+static GLOBAL: alloc_perf_test::alloc_histogram::HistogramAlloc = alloc_perf_test::alloc_histogram::HistogramAlloc;
+
+#[cfg(feature = "alloc_trace")]
+#[global_allocator]
+static GLOBAL: alloc_perf_test::alloc_trace::TraceAlloc = alloc_perf_test::alloc_trace::TraceAlloc;
 
 fn main() -> alloc_perf_test::AllocPerfRes<()> {
     /*
@@ -46,10 +54,9 @@ fn main() -> alloc_perf_test::AllocPerfRes<()> {
         .init();
 
     #[cfg(feature = "stats_alloc")]
-    let stats_alloc_reg = stats_alloc::Region::new(&GLOBAL);
+    let stats_alloc_reg = stats_alloc::Region::new(&INSTRUMENTED_SYSTEM);
     //eprintln!("INIT\n{:#?}", stats_alloc_reg.initial());
 
-    std::env::set_var("ASYNC_GLOBAL_EXECUTOR_THREADS", "16");
     let ret = async_global_executor::block_on(alloc_perf_test::cli::cli());
 
     #[cfg(feature = "stats_alloc")]
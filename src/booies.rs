@@ -17,71 +17,75 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 
-use crate::deserialize_util::{MapOrSeq, YearOrYMD};
+use crate::deserialize_util::{MapOrSeq, Opt, YearOrYMD};
 use crate::storage_util::StorageInfo;
 
 #[derive(Readable, Writable)]
-pub struct BooiesList(Vec<Booies>);
+pub struct BooiesList(Vec<Booies<String>>);
 
 impl StorageInfo for BooiesList {
     const DESC: &'static str = "a list of all booies";
 }
 
-#[derive(Readable, Writable)]
-pub struct Booies {
+/// Stored types with strings are generic over the string type, so they can be deserialized
+/// as owned values (`String`), or as views borrowing strings from serialized data (`&str`).
+#[derive(Readable, Writable, Serialize, Deserialize)]
+pub struct Booies<S> {
     pub(crate) num: u64,
-    pub(crate) name: String,
+    pub(crate) name: S,
     pub(crate) booies_id: u64,
     pub(crate) last_modified: i64,
-    pub(crate) genre: String,
+    pub(crate) genre: S,
     pub(crate) release_date: Option<YearOrYMD>,
     pub(crate) category_id: Option<u64>,
     pub(crate) category_ids: Option<Vec<u64>>,
     pub(crate) rating: Option<f64>,
 }
 
-#[derive(Readable, Writable, Debug)]
-pub(crate) struct BooiesExampleSadioInfo {
-    pub(crate) boec_name: Option<String>,
+#[derive(Readable, Writable, Debug, Serialize, Deserialize)]
+pub(crate) struct BooiesExampleSadioInfo<S> {
+    pub(crate) boec_name: Opt<S>,
     pub(crate) bad_rate: u64,
     pub(crate) channels: u64,
 }
 
-#[derive(Readable, Writable, Debug)]
-pub(crate) struct BooiesExampleFigureInfo {
-    pub(crate) boec_name: Option<String>,
+#[derive(Readable, Writable, Debug, Serialize, Deserialize)]
+pub(crate) struct BooiesExampleFigureInfo<S> {
+    pub(crate) boec_name: Opt<S>,
     pub(crate) wigth: u64,
     pub(crate) feight: u64,
 }
 
-#[derive(Readable, Writable, Debug)]
-pub(crate) struct BooiesExampleInfo {
+#[derive(Readable, Writable, Debug, Serialize, Deserialize)]
+pub(crate) struct BooiesExampleInfo<S> {
     pub(crate) duration_secs: Option<u64>,
     // HH:MM:SS
-    pub(crate) duration: Option<String>,
+    pub(crate) duration: Opt<S>,
     pub(crate) bitrate: Option<u64>,
-    pub(crate) figure: Option<BooiesExampleFigureInfo>,
-    pub(crate) sadio: Option<BooiesExampleSadioInfo>,
+    pub(crate) figure: Opt<BooiesExampleFigureInfo<S>>,
+    pub(crate) sadio: Opt<BooiesExampleSadioInfo<S>>,
 }
 
-#[derive(Readable, Writable, Debug)]
-pub(crate) struct BooiesExample {
+#[derive(Readable, Writable, Debug, Serialize, Deserialize)]
+pub(crate) struct BooiesExample<S> {
     pub(crate) id: u64,
     pub(crate) chapter: Option<u64>,
     pub(crate) example_num: Option<u64>,
-    pub(crate) title: String,
-    pub(crate) container_extension: String,
+    pub(crate) title: S,
+    pub(crate) container_extension: S,
     pub(crate) added: i64,
-    pub(crate) info: Option<BooiesExampleInfo>,
+    pub(crate) info: Opt<BooiesExampleInfo<S>>,
 }
 
-#[derive(Readable, Writable, Debug)]
-pub(crate) struct BooiesDetails {
-    pub(crate) examples: Option<MapOrSeq<Vec<BooiesExample>>>,
+#[derive(Readable, Writable, Debug, Serialize, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de> + Ord"))]
+pub(crate) struct BooiesDetails<S> {
+    pub(crate) examples: Opt<MapOrSeq<Vec<BooiesExample<S>>, S>>,
 }
 
-impl StorageInfo<true> for BooiesDetails {
+impl StorageInfo<true> for BooiesDetails<String> {
     const DESC: &'static str = "booies #BOOIES_ID# detailed info";
 }
@@ -17,29 +17,37 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 
 use std::collections::BTreeMap;
-use std::io::ErrorKind;
 
-use crate::fs_util::FsUtilError;
 use crate::conf::SubFull;
+use crate::cli::LoadMode;
 use crate::booies::BooiesDetails;
-use crate::storage_util::{IsSpeedyRwRd, StorageInfo, StorageOpsSpeedy};
+use crate::deserialize_util::MapOrSeq;
+use crate::phase_util::{Phase, PhaseStats};
+use crate::storage_util::{IsSpeedyRwRd, IsSpeedyView, MappedBinz, StorageInfo, StorageOpsSpeedy};
 
-use crate::{AllocPerfRes, AllocPerfError};
+use crate::AllocPerfRes;
 
-#[derive(Readable, Writable)]
-struct BooiesDetailsCacheItem {
+#[derive(Readable, Writable, Serialize, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de> + Ord"))]
+struct BooiesDetailsCacheItem<S> {
     fetched_at: i64,
-    boo_details: BooiesDetails,
+    boo_details: BooiesDetails<S>,
 }
 
-#[derive(Readable, Writable)]
-pub(crate) struct BooiesDetailsCache {
-    inner: BTreeMap<u64, BooiesDetailsCacheItem>,
+#[derive(Readable, Writable, Serialize, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de> + Ord"))]
+pub(crate) struct BooiesDetailsCacheOf<S> {
+    inner: BTreeMap<u64, BooiesDetailsCacheItem<S>>,
 }
 
+pub(crate) type BooiesDetailsCache = BooiesDetailsCacheOf<String>;
+/// `BooiesDetailsCache` with strings borrowed from serialized data.
+pub(crate) type BooiesDetailsCacheView<'a> = BooiesDetailsCacheOf<&'a str>;
+
 impl StorageInfo for BooiesDetailsCache {
     const DESC: &'static str = "booies details cache";
 }
@@ -48,37 +56,103 @@ impl IsSpeedyRwRd for BooiesDetailsCache {
     const FILE_NAME: &'static str = "BOOIES_CACHE";
 }
 
+impl IsSpeedyView for BooiesDetailsCache {
+    type View<'a> = BooiesDetailsCacheView<'a>;
+}
+
+impl<S> BooiesDetailsCacheOf<S> {
+    pub(crate) fn get_boo_details(&self, num: u64) -> Option<&BooiesDetails<S>> {
+        self.inner.get(&num).map(|item| &item.boo_details)
+    }
+}
+
 impl BooiesDetailsCache {
     pub(crate) fn new() -> Self {
         Self { inner: BTreeMap::new() }
     }
 
-    pub(crate) fn insert(&mut self, num: u64, boo_details: BooiesDetails) {
+    pub(crate) fn insert(&mut self, num: u64, boo_details: BooiesDetails<String>, fetched_at: i64) {
         let cache_item = BooiesDetailsCacheItem{
-            fetched_at: chrono::Utc::now().timestamp(),
+            fetched_at,
             boo_details,
         };
         let _ = self.inner.insert(num, cache_item);
     }
 
-    pub(crate) fn get_boo_details(&self, num: u64) -> Option<&BooiesDetails> {
-        self.inner.get(&num).map(|item| &item.boo_details)
+    /// Counts and totals printed by `inspect`.
+    pub(crate) fn summary(&self) -> Vec<(&'static str, String)> {
+        let opt_len = |s: &Option<String>| s.as_ref().map_or(0, String::len);
+
+        let chapters = || self.inner
+            .values()
+            .filter_map(|item| item.boo_details.examples.as_ref())
+            .flat_map(|examples| examples.values());
+        let examples = || chapters().flatten();
+
+        let str_bytes = examples()
+            .map(|example| {
+                let info_bytes = example.info.as_ref().map_or(0, |info| {
+                    opt_len(&info.duration)
+                        + info.figure.as_ref().map_or(0, |figure| opt_len(&figure.boec_name))
+                        + info.sadio.as_ref().map_or(0, |sadio| opt_len(&sadio.boec_name))
+                });
+                example.title.len() + example.container_extension.len() + info_bytes
+            })
+            .sum::<usize>();
+        let key_bytes = self.inner
+            .values()
+            .filter_map(|item| item.boo_details.examples.as_ref())
+            .map(|examples| match examples {
+                MapOrSeq::Map(map) => map.keys().map(String::len).sum(),
+                MapOrSeq::Seq(_) => 0,
+            })
+            .sum::<usize>();
+
+        let fetched_at = |ts: Option<&i64>| ts.map_or_else(|| "-".into(), |&ts| crate::inspect::fmt_timestamp(ts));
+        let fetched_at_iter = || self.inner.values().map(|item| &item.fetched_at);
+
+        vec![
+            ("booies", self.inner.len().to_string()),
+            ("chapters", chapters().count().to_string()),
+            ("examples", examples().count().to_string()),
+            ("string bytes", (str_bytes + key_bytes).to_string()),
+            ("first fetched at", fetched_at(fetched_at_iter().min())),
+            ("last fetched at", fetched_at(fetched_at_iter().max())),
+        ]
     }
 
-    pub(crate) async fn get_local_or_new(sub: &SubFull) -> AllocPerfRes<Self> {
+    /// Unlike a real cache, a missing local cache is an error, so runs never silently use an empty one.
+    ///
+    /// Loading steps are recorded in `stats` under the cache phases (see `Phase::for_cache()`).
+    pub(crate) async fn get_local(sub: &SubFull, load_mode: LoadMode, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
         let desc = Self::DESC;
         let sub_idx = sub.idx;
 
-        match Self::from_local(sub).await {
-            Ok(v) => Ok(v),
-            Err(AllocPerfError::FsUtil(FsUtilError::ExistentReadableFileOpenFailed { path: _, source })) if source.kind() == ErrorKind::NotFound => {
-                tracing::info!("{desc}: does not exist for sub {sub_idx} locally, start a new one..");
-                Ok(Self::new())
-            },
-            Err(e) => {
-                tracing::error!("{desc}: unexpected local failure for sub {sub_idx}: {e}");
-                Err(e)
-            },
-        }
+        let mut load_stats = PhaseStats::default();
+        let cache_res = Self::from_local(sub, load_mode, &mut load_stats)
+            .await
+            .inspect_err(|e| tracing::error!("{desc}: failed to load for sub {sub_idx}: {e}"));
+        stats.merge_mapped(&load_stats, Phase::for_cache);
+        cache_res
+    }
+
+    /// Like `get_local()`, but the cache file is mapped, for views of the cache to be deserialized from with `view_mapped()`.
+    pub(crate) async fn map_local(sub: &SubFull, stats: &mut PhaseStats) -> AllocPerfRes<MappedBinz<Self>> {
+        let desc = Self::DESC;
+        let sub_idx = sub.idx;
+
+        let mut load_stats = PhaseStats::default();
+        let mapped_res = <Self as StorageOpsSpeedy>::map_local(sub, &mut load_stats)
+            .await
+            .inspect_err(|e| tracing::error!("{desc}: failed to map for sub {sub_idx}: {e}"));
+        stats.merge_mapped(&load_stats, Phase::for_cache);
+        mapped_res
+    }
+
+    pub(crate) fn view_mapped<'a>(mapped: &'a MappedBinz<Self>, stats: &mut PhaseStats) -> AllocPerfRes<BooiesDetailsCacheView<'a>> {
+        let mut load_stats = PhaseStats::default();
+        let view_res = mapped.view(&mut load_stats);
+        stats.merge_mapped(&load_stats, Phase::for_cache);
+        view_res
     }
 }
@@ -17,10 +17,16 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use clap::{ValueEnum, Parser};
+use clap::{ValueEnum, Parser, Subcommand};
 use std::fmt::Debug;
+use std::path::PathBuf;
 
 use crate::conf::Subs;
+use crate::fs_util::{self, Modes};
+use crate::bench;
+use crate::scale;
+use crate::phase_util::PhaseReport;
+use crate::storage_util::WriteOpts;
 
 
 use crate::AllocPerfRes;
@@ -38,6 +44,93 @@ pub enum BooiesDetailsCacheRefresh {
     Forced,
 }
 
+/// How sub tasks are scheduled.
+///
+/// `Chunked` starts tasks in chunks of `--jobs`, waiting for a whole chunk to finish before starting the next.
+/// `Window` keeps `--jobs` tasks in flight, starting a new one as soon as any finishes.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+#[clap(rename_all="snake_case")]
+pub enum Scheduler {
+    Chunked,
+    Window,
+}
+
+/// Compression codec of stored data files.
+///
+/// Discriminants are stored in file headers, and must not change.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+#[clap(rename_all="snake_case")]
+pub enum Codec {
+    None = 0,
+    Lz4Frame = 1,
+    Lz4Block = 2,
+    Deflate = 3,
+}
+
+/// Endianness of stored data.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+#[clap(rename_all="snake_case")]
+pub enum DataEndianness {
+    Little,
+    Big,
+}
+
+/// Format of the final list of all subs.
+///
+/// `Raw` is a made-up format, `M3u8` is extended M3U, and `Xspf` is XSPF XML.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+#[clap(rename_all="snake_case")]
+pub enum ListFormat {
+    Raw,
+    M3u8,
+    Xspf,
+}
+
+/// How the formatted list is built and written.
+///
+/// `InMemory` builds the whole list in one big buffer, then writes it.
+/// `Streaming` formats each entry in its own small buffer, and writes it right away through a buffered writer.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+#[clap(rename_all="snake_case")]
+pub enum ListMode {
+    InMemory,
+    Streaming,
+}
+
+/// How data files are loaded.
+///
+/// `Read` reads whole files into buffers, and deserializes from decompressed copies of them.
+/// `Mmap` maps files, and deserializes uncompressed data straight from the mapping, into views
+/// borrowing strings from it (compressed data is still decompressed into a buffer first).
+/// `Stream` deserializes while reading and decompressing files, with no file-sized buffers
+/// (except for `lz4_block` data, which can't be decompressed incrementally).
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq)]
+#[clap(rename_all="snake_case")]
+pub enum LoadMode {
+    Read,
+    Mmap,
+    Stream,
+}
+
+/// Where the formatted list is written to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListOutput {
+    /// still built, but discarded
+    Discard,
+    Stdout,
+    File(PathBuf),
+}
+
+impl From<Option<PathBuf>> for ListOutput {
+    fn from(path_opt: Option<PathBuf>) -> Self {
+        match path_opt {
+            None => Self::Discard,
+            Some(path) if path.as_os_str() == "-" => Self::Stdout,
+            Some(path) => Self::File(path),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[clap(rename_all="kebab-case")]
 struct GeneralArgs {
@@ -48,10 +141,219 @@ struct GeneralArgs {
 
 #[derive(Parser, Debug)]
 #[clap(rename_all="kebab-case")]
+struct RunArgs {
+    /// number of subs processed in parallel
+    #[clap(short, long, default_value_t=crate::SPAWN_CHUNK_SZ)]
+    jobs: usize,
+    /// number of async executor threads (ASYNC_GLOBAL_EXECUTOR_THREADS takes precedence if set)
+    #[clap(short, long, default_value_t=crate::EXECUTOR_THREADS)]
+    threads: usize,
+    /// how sub tasks are scheduled
+    #[clap(long, value_enum, default_value_t=Scheduler::Chunked)]
+    scheduler: Scheduler,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all="kebab-case")]
+struct WriteArgs {
+    /// compression codec of data files
+    #[clap(short, long, value_enum, default_value_t=Codec::Lz4Frame)]
+    codec: Codec,
+    /// endianness of data files
+    #[clap(short, long, value_enum, default_value_t=DataEndianness::Little)]
+    endianness: DataEndianness,
+}
+
+impl WriteArgs {
+    fn opts(&self) -> WriteOpts {
+        WriteOpts { codec: self.codec, endianness: self.endianness }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all="kebab-case")]
+struct ListArgs {
+    /// format of the list
+    #[clap(short, long, value_enum, default_value_t=ListFormat::M3u8)]
+    format: ListFormat,
+    /// how the list is built and written
+    #[clap(long, value_enum, default_value_t=ListMode::InMemory)]
+    list_mode: ListMode,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all="kebab-case")]
+struct LoadArgs {
+    /// how data files are loaded
+    #[clap(long, value_enum, default_value_t=LoadMode::Read)]
+    load_mode: LoadMode,
+    /// run with all available subs if the data set has less than N, instead of failing
+    #[clap(long)]
+    clamp: bool,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all="kebab-case")]
+struct PhaseReportArgs {
+    /// report time spent in each pipeline phase, per sub and aggregated
+    #[clap(long)]
+    phase_times: bool,
+    /// report allocation stats of each pipeline phase, per sub and aggregated (runs subs one at a time)
+    #[cfg(feature = "stats_alloc")]
+    #[clap(long)]
+    phase_allocs: bool,
+    /// print all phase reports as JSON (to stderr, like the other reports)
+    #[clap(long)]
+    phase_json: bool,
+}
+
+impl PhaseReportArgs {
+    fn phase_allocs(&self) -> bool {
+        #[cfg(feature = "stats_alloc")]
+        return self.phase_allocs;
+        #[cfg(not(feature = "stats_alloc"))]
+        return false;
+    }
+
+    /// Allocation counters are process-global, so subs are run one at a time when allocation stats are reported.
+    fn jobs(&self, jobs: usize) -> usize {
+        match self.phase_allocs() && jobs > 1 {
+            true => {
+                tracing::warn!("running subs one at a time instead of {jobs} for exact allocation stats");
+                1
+            },
+            false => jobs,
+        }
+    }
+
+    fn print(&self, report: &PhaseReport) {
+        let phase_allocs = self.phase_allocs();
+
+        match (self.phase_json, self.phase_times, phase_allocs) {
+            (true, _, allocs) => report.print_json(allocs),
+            (false, false, false) => (),
+            (false, times, allocs) => {
+                if times {
+                    report.print_times();
+                }
+                if allocs {
+                    #[cfg(feature = "stats_alloc")]
+                    report.print_allocs();
+                }
+            },
+        }
+    }
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("'{s}' is not an octal mode (e.g. 600)")),
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all="kebab-case")]
+struct ModeArgs {
+    /// octal permission mode of created files (unix only)
+    #[clap(long, global=true, value_parser=parse_mode, default_value="600")]
+    file_mode: u32,
+    /// octal permission mode of created dirs (unix only)
+    #[clap(long, global=true, value_parser=parse_mode, default_value="700")]
+    dir_mode: u32,
+}
+
+impl ModeArgs {
+    fn modes(&self) -> Modes {
+        Modes { file: self.file_mode, dir: self.dir_mode }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all="kebab-case")]
+struct Cli {
+    /// dir holding data files of all subs (created if missing)
+    #[clap(long, global=true, env="ALLOC_PERF_TEST_DATA_DIR", default_value=".")]
+    data_dir: PathBuf,
+    #[clap(flatten)]
+    modes: ModeArgs,
+    #[clap(subcommand)]
+    commands: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+#[clap(rename_all="kebab-case")]
 enum Commands {
     TestAllocPerf {
         #[clap(flatten)]
         general: GeneralArgs,
+        #[clap(flatten)]
+        run: RunArgs,
+        #[clap(flatten)]
+        phase_report: PhaseReportArgs,
+        /// write the formatted list to this file, or stdout if `-` (built and discarded if not set)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        #[clap(flatten)]
+        list: ListArgs,
+        #[clap(flatten)]
+        load: LoadArgs,
+        /// record a trace of all allocator ops during the run to this file
+        #[cfg(feature = "alloc_trace")]
+        #[clap(long)]
+        record_trace: Option<PathBuf>,
+    },
+    /// run test-alloc-perf workload multiple times in-process, and report timing statistics
+    Bench {
+        #[clap(flatten)]
+        general: GeneralArgs,
+        #[clap(flatten)]
+        run: RunArgs,
+        #[clap(flatten)]
+        list: ListArgs,
+        #[clap(flatten)]
+        load: LoadArgs,
+        /// number of measured runs
+        #[clap(short, long, default_value="10")]
+        runs: usize,
+        /// number of warmup runs (not measured)
+        #[clap(short, long, default_value="1")]
+        warmup: usize,
+        /// print results as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// run test-alloc-perf workload with 1, 2, 4, ... up to MAX concurrent subs and executor threads, and report scaling
+    Scale {
+        #[clap(flatten)]
+        general: GeneralArgs,
+        /// maximum number of concurrent subs and executor threads
+        #[clap(short, long, default_value_t=crate::SPAWN_CHUNK_SZ)]
+        max: usize,
+        /// how sub tasks are scheduled
+        #[clap(long, value_enum, default_value_t=Scheduler::Chunked)]
+        scheduler: Scheduler,
+        #[clap(flatten)]
+        list: ListArgs,
+        #[clap(flatten)]
+        load: LoadArgs,
+        /// number of measured runs per level
+        #[clap(short, long, default_value="3")]
+        runs: usize,
+        /// number of warmup runs per level (not measured)
+        #[clap(short, long, default_value="1")]
+        warmup: usize,
+        /// print results as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// replay a trace recorded with `test-alloc-perf --record-trace` against the current allocator
+    ReplayTrace {
+        /// trace file path
+        path: PathBuf,
+        /// write to allocated memory, instead of only calling allocator functions
+        #[clap(long)]
+        touch: bool,
     },
     GenData {
         #[clap(flatten)]
@@ -59,21 +361,161 @@ enum Commands {
         /// rough size of generated data relative to the default (SZ/DEF)^2
         #[clap(short, default_value="100")]
         sz: usize,
+        /// seed for reproducible data generation (random if not set)
+        #[clap(long)]
+        seed: Option<u64>,
+        #[clap(flatten)]
+        write: WriteArgs,
+    },
+    /// re-write existing data files with another codec and/or endianness (data sets from older versions included)
+    ConvertData {
+        #[clap(flatten)]
+        general: GeneralArgs,
+        #[clap(flatten)]
+        write: WriteArgs,
     },
+    /// print a summary of a stored ALL or BOOIES_CACHE file
+    Inspect {
+        /// data file path
+        path: PathBuf,
+        /// dump all contents as JSON instead
+        #[clap(long)]
+        json: bool,
+    },
+    /// write contents of a stored ALL or BOOIES_CACHE file as JSON
+    ExportJson {
+        /// data file path
+        path: PathBuf,
+        /// JSON file path (stdout if not set)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// write a stored data file from JSON written by export-json (its type is taken from the file name, ALL or BOOIES_CACHE)
+    ImportJson {
+        /// JSON file path
+        json_path: PathBuf,
+        /// data file path
+        path: PathBuf,
+        #[clap(flatten)]
+        write: WriteArgs,
+    },
+}
+
+/// The name a value is passed with on the command line.
+fn value_name(v: impl ValueEnum) -> String {
+    v.to_possible_value()
+        .map(|pv| pv.get_name().to_owned())
+        .unwrap_or_default()
+}
+
+impl Commands {
+    fn executor_config(&self) -> async_global_executor::GlobalExecutorConfig {
+        let config = async_global_executor::GlobalExecutorConfig::default();
+        match self {
+            Self::TestAllocPerf { run, .. } | Self::Bench { run, .. } => config.with_min_threads(run.threads.max(1)),
+            // threads are added per level
+            Self::Scale { max, .. } => config.with_min_threads(1).with_max_threads((*max).max(1)),
+            Self::ReplayTrace { .. } | Self::GenData { .. } | Self::ConvertData { .. } | Self::Inspect { .. } | Self::ExportJson { .. } | Self::ImportJson { .. } => config.with_min_threads(crate::EXECUTOR_THREADS),
+        }
+    }
 }
 
 pub async fn cli() -> AllocPerfRes<()> {
-    let mut commands = Commands::parse();
-    tracing::debug!("{commands:#?}");
+    let cli = Cli::parse();
+    tracing::debug!("{cli:#?}");
+    let Cli { data_dir, modes, mut commands } = cli;
+    let data_dir = &*data_dir;
+
+    // must happen before anything is created
+    fs_util::set_modes(modes.modes());
+
+    // must happen before anything is spawned
+    async_global_executor::init_with_config(commands.executor_config());
 
     match &mut commands {
-        Commands::GenData { general, sz } => {
-            Subs::gen_save_all(general.n, *sz).await?;
+        Commands::GenData { general, sz, seed, write } => {
+            Subs::gen_save_all(general.n, data_dir, *sz, *seed, write.opts()).await?;
+        },
+        Commands::ConvertData { general, write } => {
+            Subs::convert_all(general.n, data_dir, write.opts()).await?;
+        },
+        Commands::Inspect { path, json } => {
+            crate::inspect::inspect(path, *json).await?;
+        },
+        Commands::ExportJson { path, output } => {
+            crate::json_util::export_json(path, output.as_deref()).await?;
+        },
+        Commands::ImportJson { json_path, path, write } => {
+            crate::json_util::import_json(json_path, path, write.opts()).await?;
+        },
+        Commands::TestAllocPerf{
+            general,
+            run,
+            phase_report,
+            output,
+            list,
+            load,
+            #[cfg(feature = "alloc_trace")]
+            record_trace,
+        } => {
+            #[cfg(feature = "alloc_trace")]
+            if let Some(trace_path) = record_trace {
+                crate::alloc_trace::start_recording(trace_path)?;
+            }
+
+            let report_res: AllocPerfRes<PhaseReport> = try {
+                Subs::load(general.n, load.clamp, data_dir)
+                    .await?
+                    .print_booies_examples_list(run.scheduler, phase_report.jobs(run.jobs), list.format, list.list_mode, load.load_mode, &output.take().into())
+                    .await?
+            };
+
+            // stopped on failed runs too, so records buffered before the failure are not lost
+            #[cfg(feature = "alloc_trace")]
+            crate::alloc_trace::stop_recording()?;
+            let report = report_res?;
+
+            phase_report.print(&report);
+            #[cfg(feature = "alloc_histogram")]
+            crate::alloc_histogram::dump();
+        },
+        Commands::Scale{ general, max, scheduler, list, load, runs, warmup, json } => {
+            let (n, scheduler, format, list_mode, load_mode) = (general.n, *scheduler, list.format, list.list_mode, load.load_mode);
+            if *max > n as usize {
+                tracing::warn!("levels above {n} (number of subs) won't add more concurrent subs");
+            }
+            let name = format!("test-alloc-perf -n {n} --scheduler {} -f {} --list-mode {} --load-mode {}",
+                value_name(scheduler), value_name(format), value_name(list_mode), value_name(load_mode));
+            let subs = &Subs::load(n, load.clamp, data_dir).await?;
+            let report = scale::scale(name, *max, *runs, *warmup, |level| async move {
+                let report = subs
+                    .print_booies_examples_list(scheduler, level, format, list_mode, load_mode, &ListOutput::Discard)
+                    .await?;
+                Ok(report.entries)
+            }).await?;
+            match json {
+                true => report.print_json(),
+                false => report.print_human(),
+            }
+        },
+        Commands::ReplayTrace{ path, touch } => {
+            crate::alloc_trace::replay(path, *touch)
+                .await?
+                .print();
         },
-        Commands::TestAllocPerf{ general } => {
-            Subs::gen_subs(general.n)
-                .print_booies_examples_list()
-                .await?;
+        Commands::Bench{ general, run, list, load, runs, warmup, json } => {
+            let (n, jobs, scheduler) = (general.n, run.jobs, run.scheduler);
+            let (format, list_mode, load_mode) = (list.format, list.list_mode, load.load_mode);
+            let name = format!("test-alloc-perf -n {n} -j {jobs} -t {} --scheduler {} -f {} --list-mode {} --load-mode {}",
+                run.threads, value_name(scheduler), value_name(format), value_name(list_mode), value_name(load_mode));
+            let subs = &Subs::load(n, load.clamp, data_dir).await?;
+            let report = bench::bench(name, *runs, *warmup, || async move {
+                subs.print_booies_examples_list(scheduler, jobs, format, list_mode, load_mode, &ListOutput::Discard).await
+            }).await?;
+            match json {
+                true => report.print_json(),
+                false => report.print_human(),
+            }
         },
     }
     Ok(())
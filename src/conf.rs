@@ -17,101 +17,385 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use std::path::PathBuf;
+use speedy::{Readable, Writable};
 
-use crate::all::{AllInfo, EBESMap};
+use std::io::ErrorKind;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use crate::all::{AllInfo, AllInfoOf, EBESMap};
 use crate::booies_cache::BooiesDetailsCache;
-use crate::fs_util;
-use crate::storage_util::StorageOpsSpeedy;
+use crate::fs_util::{self, file::UpdatableWritableFile, FsUtilError};
+use crate::storage_util::{IsSpeedyRwRd, StorageInfo, StorageOpsSpeedy, WriteOpts};
 use crate::spawn_util;
+use crate::cli::{ListFormat, ListMode, ListOutput, LoadMode, Scheduler};
+use crate::phase_util::{Phase, PhaseStats, PhaseReport};
 use crate::AllocPerfError;
 
 use crate::AllocPerfRes;
 
-#[derive(Clone, Debug)]
+#[derive(Readable, Writable, Clone, Debug)]
 pub(crate) struct SubFull {
     pub(crate) idx: u8,
     pub(crate) domain: String,
     pub(crate) username: String,
     pub(crate) password: String,
+    /// dir holding the data dirs of all subs, not stored (set after loading)
+    #[speedy(skip)]
+    pub(crate) data_dir: PathBuf,
 }
 
 impl SubFull {
+    /// Path of the data dir of this sub, which is not created if missing (unlike with `mk_sub_dir_path()`).
+    pub(crate) fn sub_dir_path(&self) -> PathBuf {
+        self.data_dir.join(self.idx.to_string())
+    }
+
     pub(crate) async fn mk_sub_dir_path(&self) -> AllocPerfRes<PathBuf> {
-        let path = PathBuf::from(self.idx.to_string());
+        let path = self.sub_dir_path();
         fs_util::dir::exists_or_create(&path).await?;
         Ok(path)
     }
 }
 
+/// Parameters a dataset was generated with.
+#[derive(Readable, Writable, Debug)]
+pub(crate) struct GenParams {
+    pub(crate) seed: u64,
+    pub(crate) n: u8,
+    /// 0 for data sets generated by older versions, which didn't record it
+    pub(crate) sz: u64,
+}
+
+/// Subs a dataset was generated for, and how.
+///
+/// Written last by `gen-data`, so a dataset with a manifest is complete.
+#[derive(Readable, Writable, Debug)]
+pub(crate) struct SubsManifest {
+    pub(crate) params: GenParams,
+    pub(crate) subs: Vec<SubFull>,
+}
+
+impl StorageInfo for SubsManifest {
+    const DESC: &'static str = "subs manifest";
+}
+
+impl IsSpeedyRwRd for SubsManifest {
+    const FILE_NAME: &'static str = "SUBS";
+}
+
 #[derive(Debug)]
 pub struct Subs(Vec<SubFull>);
 
 impl Subs {
-    pub fn gen_subs(n: u8) -> Self {
-        let subs = {1..=n}.map(|idx| {
-            SubFull {
-                idx,
-                domain: format!("https://{}.com", crate::rand_str(16..=16).to_ascii_lowercase()),
-                username: crate::rand_str(16..=16),
-                password: crate::rand_str(16..=16),
-            }
-        }).collect();
+    fn gen_sub(idx: u8, data_dir: &Path, rng: &mut fastrand::Rng) -> SubFull {
+        SubFull {
+            idx,
+            domain: format!("https://{}.com", crate::rand_str(rng, 16..=16).to_ascii_lowercase()),
+            username: crate::rand_str(rng, 16..=16),
+            password: crate::rand_str(rng, 16..=16),
+            data_dir: data_dir.to_owned(),
+        }
+    }
+
+    pub fn gen_subs(n: u8, data_dir: &Path, rng: &mut fastrand::Rng) -> Self {
+        let subs = {1..=n}.map(|idx| Self::gen_sub(idx, data_dir, rng)).collect();
         Self(subs)
     }
-    pub async fn gen_save_all(n: u8, sz: usize) -> AllocPerfRes<()> {
-        let subs = Self::gen_subs(n);
+    pub async fn gen_save_all(n: u8, data_dir: &Path, sz: usize, seed: Option<u64>, opts: WriteOpts) -> AllocPerfRes<()> {
+        let seed = seed.unwrap_or_else(|| fastrand::u64(..));
+        tracing::info!("generating data in {:?} with seed {seed}, and {opts:?}", data_dir.to_string_lossy());
+        fs_util::dir::exists_or_create(data_dir).await?;
+        let mut rng = fastrand::Rng::with_seed(seed);
+
+        let subs = Self::gen_subs(n, data_dir, &mut rng);
         for n in 1..=n {
+            // forked per sub, so a sub's data doesn't depend on how much randomness previous subs consumed
+            let mut sub_rng = rng.fork();
             tracing::info!("gen and save all for {n}");
             let path = AllInfo::get_path(&subs.0[(n-1) as usize]).await?;
-            let (all, _) = AllInfo::gen_random(sz).with_updated_binz_file(&path).await?;
+            let (all, _) = AllInfo::gen_random(sz, &mut sub_rng).with_updated_binz_file(&path, opts).await?;
             tracing::info!("gen and save boo_cache for {n}");
             let path = BooiesDetailsCache::get_path(&subs.0[(n-1) as usize]).await?;
-            let _ = all.gen_booies_details().with_updated_binz_file(&path).await?;
+            let _ = all.gen_booies_details(&mut sub_rng).with_updated_binz_file(&path, opts).await?;
         }
+
+        let manifest = SubsManifest {
+            params: GenParams { seed, n, sz: sz as u64 },
+            subs: subs.0,
+        };
+        tracing::info!("saving subs manifest with {:?}", manifest.params);
+        let _ = manifest.with_updated_binz_file(data_dir.join(SubsManifest::FILE_NAME), opts).await?;
         Ok(())
     }
-    async fn sub_to_all_info(sub: &SubFull) -> AllocPerfRes<(u8, String, AllocPerfRes<AllInfo>)> {
-        let sub_idx = sub.idx;
-        let sub_dom = sub.domain.clone();
-        let sub_all_info_res = try {
-            AllInfo::from_local(sub).await?
+
+    /// Load the manifest written by `gen-data` in `data_dir`, with `data_dir` set in its subs.
+    async fn load_manifest(data_dir: &Path) -> AllocPerfRes<SubsManifest> {
+        let path = data_dir.join(SubsManifest::FILE_NAME);
+        UpdatableWritableFile::warn_if_interrupted(&path).await?;
+        let manifest_res = SubsManifest::from_binz_file(&path, LoadMode::Read, &mut PhaseStats::default()).await;
+        let manifest = match manifest_res {
+            Ok(manifest) => manifest,
+            Err(AllocPerfError::FsUtil(FsUtilError::ExistentReadableFileOpenFailed { path: _, source })) if source.kind() == ErrorKind::NotFound => {
+                tracing::error!("no {desc} found @ '{path_str}', (re-)run gen-data with the same data dir first",
+                    desc=SubsManifest::DESC,
+                    path_str=path.to_string_lossy());
+                return Err(FsUtilError::ExistentReadableFileOpenFailed { path, source })?;
+            },
+            Err(e) => return Err(e),
+        };
+        fs_util::file::warn_if_exposed(&path).await?;
+        tracing::info!("loaded {} subs generated with {:?}", manifest.subs.len(), manifest.params);
+
+        let subs = manifest.subs
+            .into_iter()
+            .map(|sub| SubFull { data_dir: data_dir.to_owned(), ..sub })
+            .collect();
+        Ok(SubsManifest { subs, ..manifest })
+    }
+
+    /// Load the first `n` subs from the manifest written by `gen-data` in `data_dir`,
+    /// and check that their data files exist.
+    ///
+    /// Files left by interrupted `gen-data`/`convert-data` runs are only warned about, and left for writers
+    /// to recover. And since data files include credentials, a warning is logged for each one readable
+    /// by group or others.
+    ///
+    /// If the data set has less than `n` subs, all of them are loaded with `clamp`, and it's an error otherwise.
+    pub(crate) async fn load(n: u8, clamp: bool, data_dir: &Path) -> AllocPerfRes<Self> {
+        let manifest = Self::load_manifest(data_dir).await?;
+
+        let available = manifest.subs.len();
+        if n as usize > available {
+            match clamp {
+                true => tracing::warn!("only {available} subs available, running with {available} instead of {n}"),
+                false => {
+                    let e = AllocPerfError::NotEnoughSubs { requested: n, available };
+                    tracing::error!("{e}");
+                    return Err(e);
+                },
+            }
+        }
+
+        let subs = manifest.subs
+            .into_iter()
+            .take(n as usize)
+            .collect::<Vec<_>>();
+
+        for sub in &subs {
+            // not `get_path()`, which creates missing sub dirs
+            let sub_dir = sub.sub_dir_path();
+            for path in [sub_dir.join(AllInfo::FILE_NAME), sub_dir.join(BooiesDetailsCache::FILE_NAME)] {
+                UpdatableWritableFile::warn_if_interrupted(&path).await?;
+                if !fs_util::file::exists(&path).await? {
+                    let e = AllocPerfError::SubDataMissing { idx: sub.idx, path };
+                    tracing::error!("{e}");
+                    return Err(e);
+                }
+                fs_util::file::warn_if_exposed(&path).await?;
+            }
+        }
+        Ok(Self(subs))
+    }
+
+    /// A manifest for data sets generated by older versions, which didn't write one.
+    ///
+    /// Subs are taken from numbered sub dirs in `data_dir` (up to `n` of them). Older versions didn't store
+    /// sub credentials either, so new ones are generated.
+    async fn legacy_manifest(n: u8, data_dir: &Path) -> AllocPerfRes<SubsManifest> {
+        let mut idxs = fs_util::dir::sub_dir_names(data_dir)
+            .await?
+            .into_iter()
+            .filter_map(|name| {
+                // named exactly like sub dirs, so e.g. `01` is not taken as sub 1
+                let name = name.to_str()?;
+                let idx = name.parse::<u8>().ok()?;
+                (idx > 0 && idx.to_string() == name).then_some(idx)
+            })
+            .collect::<Vec<_>>();
+        idxs.sort_unstable();
+        idxs.truncate(n as usize);
+
+        if idxs.is_empty() {
+            let e = AllocPerfError::NoSubsFound(data_dir.to_owned());
+            tracing::error!("{e}");
+            return Err(e);
+        }
+
+        let seed = fastrand::u64(..);
+        tracing::warn!("no {desc} found, converting subs {idxs:?} from sub dirs, with new credentials generated with seed {seed}",
+            desc=SubsManifest::DESC);
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let subs = idxs.into_iter()
+            .map(|idx| Self::gen_sub(idx, data_dir, &mut rng))
+            .collect::<Vec<_>>();
+        let params = GenParams { seed, n: subs.len() as u8, sz: 0 };
+        Ok(SubsManifest { params, subs })
+    }
+
+    /// Convert data files of the first `n` subs in the manifest in `data_dir` (or all of them if it has less),
+    /// then the manifest itself.
+    ///
+    /// Data sets generated by older versions have no manifest, so one is written for them (see `legacy_manifest()`).
+    pub(crate) async fn convert_all(n: u8, data_dir: &Path, opts: WriteOpts) -> AllocPerfRes<()> {
+        let manifest_path = data_dir.join(SubsManifest::FILE_NAME);
+        // a manifest of an interrupted run may only be left as a backup
+        UpdatableWritableFile::recover_interrupted(&manifest_path).await?;
+        let has_manifest = fs_util::file::exists(&manifest_path).await?;
+        let manifest = match has_manifest {
+            true => Self::load_manifest(data_dir).await?,
+            false => Self::legacy_manifest(n, data_dir).await?,
         };
-        Ok((sub_idx, sub_dom, sub_all_info_res))
+        if n as usize > manifest.subs.len() {
+            tracing::warn!("only {} subs available, converting those", manifest.subs.len());
+        }
+        for sub in manifest.subs.iter().take(n as usize) {
+            tracing::info!("converting data files of sub {}", sub.idx);
+            let sub_dir = sub.sub_dir_path();
+            AllInfo::convert_binz_file(sub_dir.join(AllInfo::FILE_NAME), opts).await?;
+            BooiesDetailsCache::convert_binz_file(sub_dir.join(BooiesDetailsCache::FILE_NAME), opts).await?;
+        }
+        match has_manifest {
+            true => SubsManifest::convert_binz_file(manifest_path, opts).await?,
+            false => {
+                // written last, like with `gen-data`
+                tracing::info!("saving subs manifest with {:?}", manifest.params);
+                let _ = manifest.with_updated_binz_file(manifest_path, opts).await?;
+            },
+        }
+        Ok(())
     }
 }
 
 impl Subs {
-    pub async fn print_booies_examples_list(&self) -> AllocPerfRes<()> {
-        async fn get_sub_e_map(sub: SubFull) -> AllocPerfRes<(u8, String, AllocPerfRes<AllocPerfRes<Option<EBESMap>>>)> {
-            let (sub_idx, sub_dom, all_info_res) = Subs::sub_to_all_info(&sub).await?;
+    pub(crate) async fn print_booies_examples_list(&self, scheduler: Scheduler, jobs: usize, format: ListFormat, list_mode: ListMode, load_mode: LoadMode, output: &ListOutput) -> AllocPerfRes<PhaseReport> {
+        type SubEMapRes = AllocPerfRes<AllocPerfRes<Option<EBESMap>>>;
+        async fn e_map_from_all<S: Deref<Target=str>>(all_info_res: AllocPerfRes<AllInfoOf<S>>, sub: &SubFull, load_mode: LoadMode, stats: &mut PhaseStats) -> AllocPerfRes<SubEMapRes> {
             match all_info_res {
-                Err(e) => Ok((sub_idx, sub_dom, Err(e))),
+                Err(e) => Ok(Err(e)),
                 Ok(all_info) => {
-                    let e_map_res = EBESMap::mk_from_all(&all_info, &sub).await; 
-                    Ok((sub_idx, sub_dom, Ok(Ok(e_map_res?))))
+                    let e_map_res = EBESMap::mk_from_all(&all_info, sub, load_mode, stats).await;
+                    stats.record(Phase::Teardown, || drop(all_info));
+                    Ok(Ok(Ok(e_map_res?)))
                 },
             }
         }
+        async fn get_sub_e_map((sub, load_mode): (SubFull, LoadMode)) -> AllocPerfRes<(u8, String, PhaseStats, SubEMapRes)> {
+            let mut stats = PhaseStats::default();
+            let sub_e_map_res = match load_mode {
+                LoadMode::Mmap => match AllInfo::map_local(&sub, &mut stats).await {
+                    Err(e) => Err(e),
+                    Ok(mapped) => {
+                        // the view borrows from the mapping, so the mapping is kept until the e-map is built
+                        let all_info_res = mapped.view(&mut stats);
+                        let sub_e_map_res = e_map_from_all(all_info_res, &sub, load_mode, &mut stats).await?;
+                        stats.record(Phase::Teardown, || drop(mapped));
+                        sub_e_map_res
+                    },
+                },
+                LoadMode::Read | LoadMode::Stream => {
+                    let all_info_res = AllInfo::from_local(&sub, load_mode, &mut stats).await;
+                    e_map_from_all(all_info_res, &sub, load_mode, &mut stats).await?
+                },
+            };
+            Ok((sub.idx, sub.domain, stats, sub_e_map_res))
+        }
 
         let runner_args = self.0
             .iter()
-            .cloned();
+            .cloned()
+            .map(|sub| (sub, load_mode));
 
-        let runner = spawn_util::chunked_spawn_runner::<{crate::SPAWN_CHUNK_SZ}, false, _, _, _, _, _, _>;
-        let e_maps_info = runner(runner_args, get_sub_e_map).await
+        let runner = spawn_util::spawn_runner::<false, _, _, _, _, _, _>;
+        let e_maps_info = runner(scheduler, jobs, runner_args, get_sub_e_map).await
             .map_err(|(_, errors)| AllocPerfError::Multi(errors))?;
 
         let mut e_maps = Vec::with_capacity(e_maps_info.len());
+        let mut report = PhaseReport::default();
 
-        for (sub_idx, _sub_dom, sub_e_map_res_res) in e_maps_info {
+        for (sub_idx, _sub_dom, stats, sub_e_map_res_res) in e_maps_info {
             match sub_e_map_res_res {
                 Err(e) => tracing::error!("failed to get {} info for subscription {sub_idx}: {e}", "booies example"),
                 Ok(Err(e)) => tracing::error!("failed to get extracted {} map for subscription {sub_idx}: {e}", "booies example"),
-                Ok(Ok(e_map_opt)) => e_maps.push(e_map_opt),
+                Ok(Ok(e_map_opt)) => {
+                    report.entries += e_map_opt.as_ref().map_or(0, EBESMap::entry_count);
+                    e_maps.push((sub_idx, e_map_opt));
+                },
+            }
+            report.subs.push((sub_idx, stats));
+        }
+
+        match list_mode {
+            ListMode::InMemory => {
+                let list = report.list.record(Phase::ListBuild, || EBESMap::multi_to_formatted_list(&e_maps, format));
+                report.list.record_async(Phase::ListWrite, Self::write_list(&list, output)).await?;
+                report.list.record(Phase::Teardown, || drop(list));
+            },
+            ListMode::Streaming => {
+                // building and writing are interleaved, so all of it is recorded as writing
+                report.list.record_async(Phase::ListWrite, Self::stream_list(&e_maps, format, output)).await?;
+            },
+        }
+
+        for (sub_idx, e_map_opt) in e_maps {
+            if let Some((_, stats)) = report.subs.iter_mut().find(|(idx, _)| *idx == sub_idx) {
+                stats.record(Phase::Teardown, || drop(e_map_opt));
             }
         }
-        //println!("{}", EBESMap::multi_to_formatted_list(&e_maps));
+        Ok(report)
+    }
+
+    async fn write_list(list: &str, output: &ListOutput) -> AllocPerfRes<()> {
+        match output {
+            ListOutput::Discard => {
+                std::hint::black_box(list);
+            },
+            ListOutput::Stdout => {
+                use futures_lite::AsyncWriteExt;
+                let mut stdout = blocking::Unblock::new(std::io::stdout());
+                let write_res: std::io::Result<()> = try {
+                    stdout.write_all(list.as_bytes()).await?;
+                    stdout.flush().await?
+                };
+                write_res.map_err(AllocPerfError::StdoutWriteFailed)?;
+            },
+            ListOutput::File(path) => {
+                tracing::info!("writing list to '{}'", path.to_string_lossy());
+                fs_util::file::NewWritableFile::create_or_truncate(path)
+                    .await?
+                    .write(list.as_bytes())
+                    .await?;
+            },
+        }
+        Ok(())
+    }
+
+    async fn stream_list(e_maps: &[(u8, Option<EBESMap>)], format: ListFormat, output: &ListOutput) -> AllocPerfRes<()> {
+        match output {
+            ListOutput::Discard => {
+                EBESMap::multi_write_formatted_list(e_maps, format, futures_lite::io::sink())
+                    .await
+                    .expect("writing to a sink never fails");
+            },
+            ListOutput::Stdout => {
+                let stdout = blocking::Unblock::new(std::io::stdout());
+                EBESMap::multi_write_formatted_list(e_maps, format, stdout)
+                    .await
+                    .map_err(AllocPerfError::StdoutWriteFailed)?;
+            },
+            ListOutput::File(path) => {
+                tracing::info!("streaming list to '{}'", path.to_string_lossy());
+                let file = fs_util::file::NewWritableFile::create_or_truncate(path)
+                    .await?
+                    .into_file();
+                EBESMap::multi_write_formatted_list(e_maps, format, file)
+                    .await
+                    .map_err(|source| FsUtilError::NewWritableFileWriteFailed {
+                        path: path.clone(),
+                        source,
+                    })?;
+            },
+        }
         Ok(())
     }
 }
@@ -17,19 +17,31 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 
 use std::collections::BTreeMap;
 
 use crate::wrapper_types::NDWrapper;
 
-#[derive(Readable, Writable, Debug)]
-pub(crate) enum MapOrSeq<V: 'static> {
-    Map(BTreeMap<String, V>),
+/// `Option`, for fields of generic types.
+///
+/// speedy's derive special-cases fields spelled `Option<T>` (or `BTreeMap<K, V>`), and only bounds them
+/// as a whole, while reading their items directly. Through these aliases, such fields are read with
+/// speedy's own impls instead, which use the same encoding.
+pub(crate) type Opt<T> = Option<T>;
+
+/// `BTreeMap`, for fields of generic types. See [`Opt`].
+pub(crate) type OrdMap<K, V> = BTreeMap<K, V>;
+
+#[derive(Readable, Writable, Debug, Serialize, Deserialize)]
+#[serde(bound(deserialize = "V: Deserialize<'de>, K: Deserialize<'de> + Ord"))]
+pub(crate) enum MapOrSeq<V, K> {
+    Map(OrdMap<K, V>),
     Seq(Vec<V>),
 }
 
-impl<V: 'static> MapOrSeq<V> {
+impl<V, K> MapOrSeq<V, K> {
     pub(crate) fn values(&self) -> Box<dyn Iterator<Item=&V> + '_> {
         match self {
             Self::Map(map) => Box::new(map.values()),
@@ -38,7 +50,7 @@ impl<V: 'static> MapOrSeq<V> {
     }
 }
 
-#[derive(Debug, Readable, Writable)]
+#[derive(Debug, Readable, Writable, Serialize, Deserialize)]
 pub(crate) enum YearOrYMD {
     Year(u64),
     YMD(NDWrapper),
@@ -20,9 +20,37 @@
 use thiserror::Error;
 
 use std::path::PathBuf;
+use std::sync::OnceLock;
 
 use crate::AllocPerfRes;
 
+/// Permission modes of created files and dirs (unix only).
+///
+/// Defaults are restrictive, since generated sub records include usernames and passwords.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Modes {
+    pub(crate) file: u32,
+    pub(crate) dir: u32,
+}
+
+impl Modes {
+    pub(crate) const DEFAULT: Self = Self { file: 0o600, dir: 0o700 };
+}
+
+static MODES: OnceLock<Modes> = OnceLock::new();
+
+/// Override default modes. Only the first call has an effect, and it should happen
+/// before anything is created.
+pub(crate) fn set_modes(modes: Modes) {
+    if MODES.set(modes).is_err() {
+        tracing::warn!("file/dir modes already set, ignoring {modes:?}");
+    }
+}
+
+fn modes() -> Modes {
+    MODES.get().copied().unwrap_or(Modes::DEFAULT)
+}
+
 #[derive(Debug, Error)]
 pub enum FsUtilError {
     #[error("failed to get a usize value from u64 value '{0}'")]
@@ -69,6 +97,16 @@ pub enum FsUtilError {
         path: PathBuf,
         source: std::io::Error,
     },
+    #[error("syncing '{}' to disk failed: {source}", path.to_string_lossy())]
+    SyncFailed{
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("removing file @ '{}' failed: {source}", path.to_string_lossy())]
+    RemoveFailed{
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[error("opening readable file @ '{}' failed: {source}", path.to_string_lossy())]
     ExistentReadableFileOpenFailed{
         path: PathBuf,
@@ -79,6 +117,16 @@ pub enum FsUtilError {
         path: PathBuf,
         source: std::io::Error,
     },
+    #[error("mapping readable file @ '{}' failed: {source}", path.to_string_lossy())]
+    ExistentReadableFileMmapFailed{
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("listing entries of dir @ '{}' failed: {source}", path.to_string_lossy())]
+    ReadDirFailed{
+        path: PathBuf,
+        source: std::io::Error,
+    },
 }
 
 pub(super) mod util {
@@ -117,21 +165,96 @@ pub(super) mod util {
             },
         }
     }
+
+    pub(super) async fn remove_file(path: impl AsRef<Path>) -> AllocPerfRes<()> {
+        async_fs::remove_file(&path)
+            .await
+            .map_err(|source| FsUtilError::RemoveFailed{
+                path: path.as_ref().to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub(super) async fn rename(src_path: impl AsRef<Path>, dst_path: impl AsRef<Path>) -> AllocPerfRes<()> {
+        async_fs::rename(&src_path, &dst_path)
+            .await
+            .map_err(|source| FsUtilError::RenameFailed{
+                src_path: src_path.as_ref().to_owned(),
+                dst_path: dst_path.as_ref().to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// Sync the dir containing `path`, so entries added/renamed in it survive a crash.
+    #[cfg(unix)]
+    pub(super) async fn sync_parent_dir(path: impl AsRef<Path>) -> AllocPerfRes<()> {
+        let dir_path = match path.as_ref().parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        let sync_res: std::io::Result<()> = try {
+            async_fs::File::open(dir_path).await?
+                .sync_all().await?
+        };
+        sync_res.map_err(|source| FsUtilError::SyncFailed{
+            path: dir_path.to_owned(),
+            source,
+        })?;
+        Ok(())
+    }
+
+    /// Not done on other platforms, where dirs can't simply be opened and synced like files.
+    #[cfg(not(unix))]
+    pub(super) async fn sync_parent_dir(_path: impl AsRef<Path>) -> AllocPerfRes<()> {
+        Ok(())
+    }
 }
 
 
 pub(crate) mod dir {
+    use futures_lite::StreamExt;
+
+    use std::ffi::OsString;
     use std::path::Path;
     use super::util;
     use super::{FsUtilError, AllocPerfRes};
 
-    // TODO: 700 perms
+    /// Names of dirs in the dir at `path` (symlinks to dirs excluded).
+    pub(crate) async fn sub_dir_names(path: impl AsRef<Path>) -> AllocPerfRes<Vec<OsString>> {
+        let names_res: std::io::Result<Vec<OsString>> = try {
+            let mut entries = async_fs::read_dir(&path).await?;
+            let mut names = Vec::new();
+            while let Some(entry) = entries.try_next().await? {
+                if entry.file_type().await?.is_dir() {
+                    names.push(entry.file_name());
+                }
+            }
+            names
+        };
+        let names = names_res
+            .map_err(|source| FsUtilError::ReadDirFailed{
+                path: path.as_ref().to_owned(),
+                source,
+            })?;
+        Ok(names)
+    }
+
     pub(crate) async fn exists_or_create(path: impl AsRef<Path>) -> AllocPerfRes<()> {
         if util::dir_exists(&path).await? {
             return Ok(())
         }
 
-        async_fs::create_dir(&path)
+        let mut builder = async_fs::DirBuilder::new();
+        // parents too, e.g. with a new `--data-dir`
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use async_fs::unix::DirBuilderExt;
+            builder.mode(super::modes().dir);
+        }
+        builder.create(&path)
             .await
             .map_err(|e| FsUtilError::NewDirCreationFailed{
                 path: path.as_ref().to_owned(),
@@ -146,79 +269,164 @@ pub(crate) mod file {
     #[cfg(unix)] use async_fs::unix::MetadataExt;
 
     use std::path::{Path, PathBuf};
-    use crate::try_util::BoolExt;
     use super::{FsUtilError, AllocPerfRes};
 
+    pub(crate) async fn exists(path: impl AsRef<Path>) -> AllocPerfRes<bool> {
+        super::util::file_exists(path).await
+    }
+
+    /// Warn if `path` is readable by group or others (unix only).
+    ///
+    /// This is a separate check, not done when files are opened, so it's not repeated in measured loads.
+    pub(crate) async fn warn_if_exposed(path: impl AsRef<Path>) -> AllocPerfRes<()> {
+        #[cfg(unix)]
+        {
+            let path = path.as_ref();
+            let meta = async_fs::metadata(path)
+                .await
+                .map_err(|source| FsUtilError::UnexpectedErrorGettingMetaInfo{
+                    path: path.to_owned(),
+                    source,
+                })?;
+            warn_if_meta_exposed(path, &meta);
+        }
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn warn_if_meta_exposed(path: &Path, meta: &std::fs::Metadata) {
+        let mode = meta.mode() & 0o7777;
+        if mode & 0o044 != 0 {
+            tracing::warn!("{path_str:?} is readable by group and/or others (mode {mode:o}), and may contain credentials",
+                path_str=path.to_string_lossy());
+        }
+    }
+
     pub(crate) enum UpdatedOrRolledBack {
         Updated,
+        /// the write failed, and the old file was left as is
         RolledBack {
             _write_error: crate::AllocPerfError,
         },
     }
 
+    /// Writes to a temp file next to the target, then renames it over the target.
+    ///
+    /// So readers only ever see the old file or the complete new one, even if we are interrupted.
     pub(crate) struct UpdatableWritableFile{
-        backup_path: Option<PathBuf>,
-        file: NewWritableFile,
+        path: PathBuf,
+        updating: bool,
+        tmp_file: NewWritableFile,
     }
 
     impl UpdatableWritableFile {
+        const TMP_EXT: &str = "tmp";
+        /// only written by older versions, which renamed the old file to it before writing
         const BACKUP_EXT: &str = "backup";
+
+        /// `path` with `.{ext}` appended to its file name.
+        ///
+        /// Existing extensions are kept, so e.g. targets `x.bin` and `x.json` don't share a temp file.
+        fn with_ext(path: &Path, ext: &'static str) -> AllocPerfRes<PathBuf> {
+            let mut file_name = path.file_name()
+                .ok_or_else(|| FsUtilError::ExtensionNotSet {
+                    path: path.to_owned(),
+                    ext,
+                })?
+                .to_owned();
+            file_name.push(".");
+            file_name.push(ext);
+            Ok(path.with_file_name(file_name))
+        }
+
+        /// Clean up after an interrupted update of `path`.
+        ///
+        /// A left temp file is always incomplete, and is removed. A left backup file is
+        /// restored if `path` is missing, and removed otherwise.
+        pub(crate) async fn recover_interrupted(path: impl AsRef<Path>) -> AllocPerfRes<()> {
+            let path = path.as_ref();
+            let path_str = path.to_string_lossy();
+
+            let tmp_path = Self::with_ext(path, Self::TMP_EXT)?;
+            if super::util::file_exists(&tmp_path).await? {
+                tracing::warn!("removing stale temp file of {path_str:?}");
+                super::util::remove_file(&tmp_path).await?;
+            }
+
+            let backup_path = Self::with_ext(path, Self::BACKUP_EXT)?;
+            if super::util::file_exists(&backup_path).await? {
+                match super::util::file_exists(path).await? {
+                    true => {
+                        tracing::warn!("removing stale backup of {path_str:?}");
+                        super::util::remove_file(&backup_path).await?;
+                    },
+                    false => {
+                        tracing::warn!("restoring {path_str:?} from stale backup");
+                        super::util::rename(&backup_path, path).await?;
+                    },
+                }
+            }
+            Ok(())
+        }
+
+        /// Like `recover_interrupted()`, but only warns about files left by interrupted updates of `path`.
+        ///
+        /// For readers, which must not touch them, since they may belong to an update still in progress.
+        pub(crate) async fn warn_if_interrupted(path: impl AsRef<Path>) -> AllocPerfRes<()> {
+            let path = path.as_ref();
+            for ext in [Self::TMP_EXT, Self::BACKUP_EXT] {
+                let left_path = Self::with_ext(path, ext)?;
+                if super::util::file_exists(&left_path).await? {
+                    tracing::warn!("{left_path_str:?} was left by an interrupted (or running) update of {path_str:?}, \
+                        it's cleaned up when the file is written next",
+                        left_path_str=left_path.to_string_lossy(),
+                        path_str=path.to_string_lossy());
+                }
+            }
+            Ok(())
+        }
+
         pub(crate) async fn update_or_create(path: impl AsRef<Path>) -> AllocPerfRes<Self> {
             let path = path.as_ref();
-            let backup_path = match super::util::file_exists(path).await? {
-                true => {
-                    let mut backup_path = path.to_owned();
-                    backup_path.set_extension(Self::BACKUP_EXT)
-                        .err_if_not(|| FsUtilError::ExtensionNotSet {
-                            path: backup_path.clone(),
-                            ext: Self::BACKUP_EXT,
-                        })?;
-                    async_fs::rename(path, &backup_path).await
-                        .map_err(|source| FsUtilError::RenameFailed{
-                            src_path: path.to_owned(),
-                            dst_path: backup_path.clone(),
-                            source,
-                        })?;
-
-                    let path_str = path.to_string_lossy();
-                    let bpath_str = backup_path.to_string_lossy();
-                    tracing::info!("Backed up {path_str:?} to {bpath_str:?}");
-
-                    Some(backup_path)
-                },
-                false => {
-                    None
-                },
-            };
+            Self::recover_interrupted(path).await?;
 
-            let file = NewWritableFile::create(path).await?;
-            Ok(Self{ backup_path, file })
+            let updating = super::util::file_exists(path).await?;
+            let tmp_file = NewWritableFile::create(Self::with_ext(path, Self::TMP_EXT)?).await?;
+            Ok(Self{ path: path.to_owned(), updating, tmp_file })
         }
 
         pub(crate) fn updating(&self) -> bool {
-            self.backup_path.is_some()
+            self.updating
         }
 
         pub(crate) async fn update_or_rollback(self, bytes: &[u8]) -> AllocPerfRes<UpdatedOrRolledBack> {
-            let file_path = self.file.path.clone();
-            match self.file.write(bytes).await {
+            let Self { path, updating, tmp_file } = self;
+            let tmp_path = tmp_file.path.clone();
+
+            let update_res = try {
+                tmp_file.write_synced(bytes).await?;
+                super::util::rename(&tmp_path, &path).await?;
+                // make the rename itself durable
+                super::util::sync_parent_dir(&path).await?
+            };
+
+            match update_res {
                 Ok(()) => Ok(UpdatedOrRolledBack::Updated),
-                Err(_write_error) => match self.backup_path {
-                    Some(backup_path) => {
-                        let fp_str = file_path.to_string_lossy();
-                        let bp_str = backup_path.to_string_lossy();
-                        tracing::error!("write to {fp_str:?} failed: '{_write_error}'");
-                        tracing::warn!("rolling back from {bp_str:?}");
-
-                        async_fs::rename(&backup_path, &*file_path).await
-                            .map_err(|source| FsUtilError::RenameFailed{
-                                src_path: backup_path,
-                                dst_path: file_path,
-                                source,
-                            })?;
-                        Ok(UpdatedOrRolledBack::RolledBack{_write_error})
-                    },
-                    None => Err(_write_error),
+                Err(_write_error) => {
+                    let p_str = path.to_string_lossy();
+                    tracing::error!("write to {p_str:?} failed: '{_write_error}'");
+                    if super::util::file_exists(&tmp_path).await? {
+                        super::util::remove_file(&tmp_path).await?;
+                    }
+                    match updating {
+                        true => {
+                            tracing::warn!("rolled back, {p_str:?} was left as is");
+                            Ok(UpdatedOrRolledBack::RolledBack{_write_error})
+                        },
+                        false => Err(_write_error),
+                    }
                 },
             }
         }
@@ -230,11 +438,20 @@ pub(crate) mod file {
     }
 
     impl NewWritableFile {
-        // TODO: 600 perms
+        fn write_opts() -> async_fs::OpenOptions {
+            let mut opts = async_fs::OpenOptions::new();
+            opts.write(true);
+            #[cfg(unix)]
+            {
+                use async_fs::unix::OpenOptionsExt;
+                opts.mode(super::modes().file);
+            }
+            opts
+        }
+
         pub(crate) async fn create(path: impl AsRef<Path>) -> AllocPerfRes<Self> {
-            let file = async_fs::OpenOptions::new()
+            let file = Self::write_opts()
                 .create_new(true)
-                .write(true)
                 .open(&path)
                 .await
                 .map_err(|source| FsUtilError::NewWritableFileCreationFailed{
@@ -245,7 +462,50 @@ pub(crate) mod file {
             Ok(Self{ path, file })
         }
 
-        pub(crate) async fn write(mut self, bytes: &[u8]) -> AllocPerfRes<()> {
+        /// Unlike `create()`, an existing file at `path` is truncated and written over.
+        ///
+        /// The file mode only applies if the file is created here. Existing files (which may not even
+        /// be regular files, e.g. `/dev/null`) keep their mode, with a warning if it exposes a regular file.
+        pub(crate) async fn create_or_truncate(path: impl AsRef<Path>) -> AllocPerfRes<Self> {
+            let path = path.as_ref();
+            let open_err = |source| FsUtilError::NewWritableFileCreationFailed{
+                path: path.to_owned(),
+                source,
+            };
+            let file = match Self::write_opts().create_new(true).open(path).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let file = Self::write_opts()
+                        .truncate(true)
+                        .open(path)
+                        .await
+                        .map_err(open_err)?;
+                    #[cfg(unix)]
+                    {
+                        let meta = file.metadata()
+                            .await
+                            .map_err(|source| FsUtilError::UnexpectedErrorGettingMetaInfo{
+                                path: path.to_owned(),
+                                source,
+                            })?;
+                        if meta.is_file() {
+                            warn_if_meta_exposed(path, &meta);
+                        }
+                    }
+                    file
+                },
+                Err(e) => Err(open_err(e))?,
+            };
+            let path = path.to_owned();
+            Ok(Self{ path, file })
+        }
+
+        /// For writing in parts. Errors would then lack path context.
+        pub(crate) fn into_file(self) -> async_fs::File {
+            self.file
+        }
+
+        async fn write_and_flush(&mut self, bytes: &[u8]) -> AllocPerfRes<()> {
             self.file.write_all(&*bytes).await
                 .map_err(|source| FsUtilError::NewWritableFileWriteFailed{
                     path: self.path.clone(),
@@ -253,6 +513,21 @@ pub(crate) mod file {
                 })?;
             self.file.flush().await
                 .map_err(|source| FsUtilError::NewWritableFileFlushFailed{
+                    path: self.path.clone(),
+                    source,
+                })?;
+            Ok(())
+        }
+
+        pub(crate) async fn write(mut self, bytes: &[u8]) -> AllocPerfRes<()> {
+            self.write_and_flush(bytes).await
+        }
+
+        /// Like `write()`, but also waits for written bytes to reach the disk.
+        pub(crate) async fn write_synced(mut self, bytes: &[u8]) -> AllocPerfRes<()> {
+            self.write_and_flush(bytes).await?;
+            self.file.sync_all().await
+                .map_err(|source| FsUtilError::SyncFailed{
                     path: self.path,
                     source,
                 })?;
@@ -310,5 +585,31 @@ pub(crate) mod file {
 
             Ok(bytes)
         }
+
+        /// Opens `path` for reading from blocking threads.
+        pub(crate) fn open_blocking(path: impl AsRef<Path>) -> AllocPerfRes<std::fs::File> {
+            let path = path.as_ref();
+            let file = std::fs::OpenOptions::new()
+                .create(false)
+                .read(true)
+                .open(path)
+                .map_err(|source| FsUtilError::ExistentReadableFileOpenFailed{
+                    path: path.to_owned(),
+                    source,
+                })?;
+            Ok(file)
+        }
+
+        pub(crate) fn mmap(self) -> AllocPerfRes<memmap2::Mmap> {
+            // SAFETY: data files are only written by gen-data/convert-data, which are not run
+            // while loading. Other processes modifying mapped files is not supported.
+            let mapped = unsafe { memmap2::Mmap::map(&self.file) };
+            let mapped = mapped
+                .map_err(|source| FsUtilError::ExistentReadableFileMmapFailed{
+                    path: self.path,
+                    source,
+                })?;
+            Ok(mapped)
+        }
     }
 }
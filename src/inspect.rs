@@ -0,0 +1,78 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::path::Path;
+
+use crate::all::AllInfo;
+use crate::booies_cache::BooiesDetailsCache;
+use crate::cli::LoadMode;
+use crate::phase_util::PhaseStats;
+use crate::storage_util::{BinzFileInfo, IsSpeedyRwRd, StorageOpsSpeedy};
+use crate::{AllocPerfError, AllocPerfRes};
+
+/// `ts` as a UTC date/time, followed by `ts` itself.
+pub(crate) fn fmt_timestamp(ts: i64) -> String {
+    match chrono::DateTime::from_timestamp(ts, 0) {
+        Some(dt) => format!("{dt} ({ts})"),
+        None => ts.to_string(),
+    }
+}
+
+fn print_summary(path: &Path, info: &BinzFileInfo, summary: Vec<(&'static str, String)>) {
+    let BinzFileInfo { type_id, version, endianness, codec, uncompressed_len, checksum, file_len } = info;
+    let ratio = *uncompressed_len as f64 / (*file_len).max(1) as f64;
+    let header = [
+        ("type", type_id.clone()),
+        ("format version", version.to_string()),
+        ("endianness", format!("{endianness:?}")),
+        ("codec", format!("{codec:?}")),
+        ("file bytes", file_len.to_string()),
+        ("uncompressed bytes", format!("{uncompressed_len} ({ratio:.2}x)")),
+        ("checksum", format!("{checksum:#018x}")),
+    ];
+
+    println!("{}:", path.to_string_lossy());
+    for (name, value) in header.into_iter().chain(summary) {
+        println!("  {:<22}{value}", format!("{name}:"));
+    }
+}
+
+/// Print a summary of the stored data file at `path`, or dump its contents as JSON.
+pub(crate) async fn inspect(path: &Path, json: bool) -> AllocPerfRes<()> {
+    if json {
+        return crate::json_util::export_json(path, None).await;
+    }
+
+    let info = BinzFileInfo::from_binz_file(path).await?;
+    tracing::debug!("{info:?}");
+
+    let mut stats = PhaseStats::default();
+    match info.type_id.as_str() {
+        AllInfo::FILE_NAME => {
+            let all = AllInfo::from_binz_file(path, LoadMode::Read, &mut stats).await?;
+            print_summary(path, &info, all.summary());
+        },
+        BooiesDetailsCache::FILE_NAME => {
+            let cache = BooiesDetailsCache::from_binz_file(path, LoadMode::Read, &mut stats).await?;
+            print_summary(path, &info, cache.summary());
+        },
+        _ => Err(AllocPerfError::UnsupportedDataType(info.type_id))?,
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::path::Path;
+
+use crate::all::AllInfo;
+use crate::booies_cache::BooiesDetailsCache;
+use crate::cli::LoadMode;
+use crate::fs_util::file::{ExistentReadableFile, NewWritableFile};
+use crate::phase_util::PhaseStats;
+use crate::storage_util::{BinzFileInfo, IsSpeedyRwRd, StorageOpsSpeedy, WriteOpts};
+use crate::{AllocPerfError, AllocPerfRes};
+
+/// The JSON is checked to import back to the exact stored data first.
+async fn export<T: StorageOpsSpeedy + Serialize + DeserializeOwned>(path: &Path, info: BinzFileInfo, output: Option<&Path>) -> AllocPerfRes<()> {
+    let val = T::from_binz_file(path, LoadMode::Read, &mut PhaseStats::default()).await?;
+    let path_owned = path.to_owned();
+    let json = blocking::unblock(move || {
+        let json = serde_json::to_vec_pretty(&val)
+            .map_err(AllocPerfError::JsonSerialize)?;
+        let imported = serde_json::from_slice::<T>(&json)
+            .map_err(AllocPerfError::JsonDeserialize)?;
+        match info.matches_blocking(&imported)? {
+            true => Ok(json),
+            false => Err(AllocPerfError::JsonRoundTripMismatch(path_owned)),
+        }
+    }).await?;
+
+    match output {
+        Some(output) => NewWritableFile::create_or_truncate(output).await?.write(&json).await?,
+        None => {
+            use futures_lite::AsyncWriteExt;
+            let mut stdout = blocking::Unblock::new(std::io::stdout());
+            let write_res: std::io::Result<()> = try {
+                stdout.write_all(&json).await?;
+                stdout.write_all(b"\n").await?;
+                stdout.flush().await?
+            };
+            write_res.map_err(AllocPerfError::StdoutWriteFailed)?;
+        },
+    }
+    Ok(())
+}
+
+async fn import<T: StorageOpsSpeedy + DeserializeOwned>(json_path: &Path, path: &Path, opts: WriteOpts) -> AllocPerfRes<()> {
+    let json = ExistentReadableFile::open(json_path).await?.read().await?;
+    let val = blocking::unblock(move || serde_json::from_slice::<T>(&json))
+        .await
+        .map_err(AllocPerfError::JsonDeserialize)?;
+    let _ = val.with_updated_binz_file(path, opts).await?;
+    Ok(())
+}
+
+/// Write contents of the stored data file at `path` as JSON to `output`, or stdout if not set.
+///
+/// The data type is taken from the file's storage header.
+pub(crate) async fn export_json(path: &Path, output: Option<&Path>) -> AllocPerfRes<()> {
+    let info = BinzFileInfo::from_binz_file(path).await?;
+    match info.type_id.as_str() {
+        AllInfo::FILE_NAME => export::<AllInfo>(path, info, output).await,
+        BooiesDetailsCache::FILE_NAME => export::<BooiesDetailsCache>(path, info, output).await,
+        _ => Err(AllocPerfError::UnsupportedDataType(info.type_id)),
+    }
+}
+
+/// Write a stored data file at `path` from JSON written by `export_json()`.
+///
+/// The data type is taken from the file name of `path`, which is what data files are named after anyway.
+pub(crate) async fn import_json(json_path: &Path, path: &Path, opts: WriteOpts) -> AllocPerfRes<()> {
+    let file_name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match file_name.as_str() {
+        AllInfo::FILE_NAME => import::<AllInfo>(json_path, path, opts).await,
+        BooiesDetailsCache::FILE_NAME => import::<BooiesDetailsCache>(json_path, path, opts).await,
+        _ => Err(AllocPerfError::UnsupportedDataType(file_name)),
+    }
+}
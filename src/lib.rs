@@ -19,26 +19,46 @@
 
 #![feature(try_blocks)]
 #![feature(let_chains)]
-#![feature(iter_array_chunks)]
 #![feature(lazy_cell)] // for bootstrapping from stable
+#![warn(unsafe_op_in_unsafe_fn)]
+
+#[cfg(any(
+    all(feature = "stats_alloc", feature = "alloc_histogram"),
+    all(feature = "stats_alloc", feature = "alloc_trace"),
+    all(feature = "alloc_histogram", feature = "alloc_trace"),
+))]
+compile_error!("`stats_alloc`, `alloc_histogram` and `alloc_trace` features all set a global allocator, only one of them can be enabled");
 
-mod try_util;
 mod deserialize_util;
 mod wrapper_types;
 mod fs_util;
 mod storage_util;
 mod spawn_util;
+mod phase_util;
+#[cfg(feature = "stats_alloc")]
+pub mod alloc_stats;
+#[cfg(feature = "alloc_histogram")]
+pub mod alloc_histogram;
+pub mod alloc_trace;
 mod conf;
 mod category;
 mod booies;
 mod all;
 mod booies_cache;
+mod bench;
+mod scale;
+mod inspect;
+mod json_util;
 pub mod cli;
 
 use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use thiserror::Error;
 
+/// default number of sub tasks run in parallel (`--jobs`)
 pub(crate) const  SPAWN_CHUNK_SZ: usize = 8;
+/// default number of async executor threads (`--threads`)
+pub(crate) const  EXECUTOR_THREADS: usize = 16;
 
 pub type AllocPerfRes<T> = Result<T, AllocPerfError>;
 
@@ -48,12 +68,40 @@ pub enum AllocPerfError {
     FsUtil(#[from] crate::fs_util::FsUtilError),
     #[error("storage_util error: {0}")]
     StorageUtil(#[from] crate::storage_util::StorageUtilError),
+    #[error("alloc_trace error: {0}")]
+    AllocTrace(#[from] crate::alloc_trace::AllocTraceError),
+    #[error("writing to stdout failed: {0}")]
+    StdoutWriteFailed(std::io::Error),
+    #[error("spawning executor threads failed: {0}")]
+    SpawnThreads(std::io::Error),
+    #[error("{requested} subs requested, but the data set only has {available} (pass --clamp to run with those)")]
+    NotEnoughSubs {
+        requested: u8,
+        available: usize,
+    },
+    #[error("unsupported data type '{0}', only '{all}' and '{cache}' files are supported",
+        all=<crate::all::AllInfo as crate::storage_util::IsSpeedyRwRd>::FILE_NAME,
+        cache=<crate::booies_cache::BooiesDetailsCache as crate::storage_util::IsSpeedyRwRd>::FILE_NAME)]
+    UnsupportedDataType(String),
+    #[error("failed to serialize to JSON: {0}")]
+    JsonSerialize(#[source] serde_json::Error),
+    #[error("failed to deserialize from JSON: {0}")]
+    JsonDeserialize(#[source] serde_json::Error),
+    #[error("data in '{}' changes when re-imported from JSON, so it can't be exported losslessly", .0.to_string_lossy())]
+    JsonRoundTripMismatch(PathBuf),
+    #[error("data file of sub {idx} is missing @ '{}', (re-)run gen-data", path.to_string_lossy())]
+    SubDataMissing {
+        idx: u8,
+        path: PathBuf,
+    },
+    #[error("no subs manifest or numbered sub dirs found @ '{}', run gen-data first", .0.to_string_lossy())]
+    NoSubsFound(PathBuf),
     #[error("multiple errors:\n {}", .0.iter().map(|e| format!("{e}")).collect::<Vec<_>>().join("\n "))]
     Multi(Vec<Self>),
 }
 
-pub fn rand_str(len_range: RangeInclusive<usize>) -> String {
-    {0..fastrand::usize(len_range)}
-        .map(|_| fastrand::alphanumeric())
+pub fn rand_str(rng: &mut fastrand::Rng, len_range: RangeInclusive<usize>) -> String {
+    {0..rng.usize(len_range)}
+        .map(|_| rng.alphanumeric())
         .collect()
 }
@@ -0,0 +1,256 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde_json::{json, Map, Value};
+
+use std::fmt::Write;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "stats_alloc")]
+use crate::alloc_stats::{AllocMeasurement, AllocStats};
+
+/// Workload pipeline phases.
+///
+/// `Read`, `Decompress` and `Deserialize` cover loading the `ALL` file.
+/// Loading the booies details cache is broken down the same way in the `Cache*` phases.
+/// `ListBuild` and `ListWrite` happen once for all subs, after all e-maps are built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Read,
+    Decompress,
+    Deserialize,
+    CacheRead,
+    CacheDecompress,
+    CacheDeserialize,
+    EBESMapBuild,
+    ListBuild,
+    ListWrite,
+    Teardown,
+}
+
+impl Phase {
+    pub(crate) const COUNT: usize = 10;
+    pub(crate) const ALL: [Self; Self::COUNT] = [
+        Self::Read,
+        Self::Decompress,
+        Self::Deserialize,
+        Self::CacheRead,
+        Self::CacheDecompress,
+        Self::CacheDeserialize,
+        Self::EBESMapBuild,
+        Self::ListBuild,
+        Self::ListWrite,
+        Self::Teardown,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Decompress => "decompress",
+            Self::Deserialize => "deserialize",
+            Self::CacheRead => "cache read",
+            Self::CacheDecompress => "cache decomp",
+            Self::CacheDeserialize => "cache deser",
+            Self::EBESMapBuild => "e-map build",
+            Self::ListBuild => "list build",
+            Self::ListWrite => "list write",
+            Self::Teardown => "teardown",
+        }
+    }
+
+    /// The phase loading steps of the booies details cache are recorded in.
+    pub(crate) fn for_cache(self) -> Self {
+        match self {
+            Self::Read => Self::CacheRead,
+            Self::Decompress => Self::CacheDecompress,
+            Self::Deserialize => Self::CacheDeserialize,
+            _ => self,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PhaseStats {
+    times: [Duration; Phase::COUNT],
+    #[cfg(feature = "stats_alloc")]
+    allocs: [AllocStats; Phase::COUNT],
+}
+
+impl PhaseStats {
+    pub(crate) fn record<R>(&mut self, phase: Phase, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "stats_alloc")]
+        let measurement = AllocMeasurement::start();
+        let start = Instant::now();
+        let ret = f();
+        self.times[phase as usize] += start.elapsed();
+        #[cfg(feature = "stats_alloc")]
+        self.allocs[phase as usize].merge(&measurement.finish());
+        ret
+    }
+
+    pub(crate) async fn record_async<R>(&mut self, phase: Phase, fut: impl Future<Output = R>) -> R {
+        #[cfg(feature = "stats_alloc")]
+        let measurement = AllocMeasurement::start();
+        let start = Instant::now();
+        let ret = fut.await;
+        self.times[phase as usize] += start.elapsed();
+        #[cfg(feature = "stats_alloc")]
+        self.allocs[phase as usize].merge(&measurement.finish());
+        ret
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.merge_mapped(other, |phase| phase);
+    }
+
+    /// Like `merge()`, with stats of each phase in `other` merged into the `to_phase(phase)` stats.
+    pub(crate) fn merge_mapped(&mut self, other: &Self, to_phase: impl Fn(Phase) -> Phase) {
+        for phase in Phase::ALL {
+            let to = to_phase(phase) as usize;
+            self.times[to] += other.times[phase as usize];
+            #[cfg(feature = "stats_alloc")]
+            self.allocs[to].merge(&other.allocs[phase as usize]);
+        }
+    }
+
+    pub(crate) fn time(&self, phase: Phase) -> Duration {
+        self.times[phase as usize]
+    }
+
+    pub(crate) fn total_time(&self) -> Duration {
+        self.times.iter().sum()
+    }
+
+    #[cfg(feature = "stats_alloc")]
+    pub(crate) fn allocs(&self, phase: Phase) -> &AllocStats {
+        &self.allocs[phase as usize]
+    }
+
+    #[cfg_attr(not(feature = "stats_alloc"), allow(unused_variables))]
+    fn to_json(&self, allocs: bool) -> Value {
+        let phases = Phase::ALL
+            .iter()
+            .map(|&phase| {
+                #[allow(unused_mut)]
+                let mut phase_json = json!({ "secs": self.time(phase).as_secs_f64() });
+                #[cfg(feature = "stats_alloc")]
+                if allocs {
+                    phase_json["allocs"] = json!(self.allocs(phase));
+                }
+                (phase.name().to_owned(), phase_json)
+            })
+            .collect::<Map<_, _>>();
+        json!({
+            "phases": phases,
+            "total_secs": self.total_time().as_secs_f64(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PhaseReport {
+    pub(crate) subs: Vec<(u8, PhaseStats)>,
+    /// stats of phases done once for all subs
+    pub(crate) list: PhaseStats,
+    /// number of list entries produced by the run
+    pub(crate) entries: usize,
+}
+
+impl PhaseReport {
+    /// Phase stats summed over all subs, and list phases.
+    ///
+    /// Note that subs run concurrently, so the summed times can exceed wall time.
+    pub(crate) fn aggregated(&self) -> PhaseStats {
+        let mut total = self.list.clone();
+        self.subs.iter().for_each(|(_, stats)| total.merge(stats));
+        total
+    }
+
+    pub(crate) fn print_times(&self) {
+        // writing to a `String` can't fail
+        let header = Phase::ALL
+            .iter()
+            .fold(String::new(), |mut header, phase| {
+                let _ = write!(header, "{:>14}", phase.name());
+                header
+            });
+        eprintln!("{:>5} |{header}{:>14}", "sub", "total");
+
+        let row = |label: &str, stats: &PhaseStats| {
+            let cols = Phase::ALL
+                .iter()
+                .fold(String::new(), |mut cols, &phase| {
+                    let _ = write!(cols, "{:>14.3}", stats.time(phase).as_secs_f64());
+                    cols
+                });
+            eprintln!("{label:>5} |{cols}{:>14.3}", stats.total_time().as_secs_f64());
+        };
+
+        self.subs
+            .iter()
+            .for_each(|(sub_idx, stats)| row(&sub_idx.to_string(), stats));
+        row("list", &self.list);
+        row("all", &self.aggregated());
+        eprintln!("(times in seconds)");
+    }
+
+    #[cfg(feature = "stats_alloc")]
+    pub(crate) fn print_allocs(&self) {
+        let print_sub = |label: &str, stats: &PhaseStats| {
+            eprintln!("{label}:");
+            eprintln!("{:>12} |{:>10}{:>10}{:>10}{:>14}{:>14}{:>14}{:>14}",
+                "phase", "allocs", "deallocs", "reallocs", "alloc'd", "dealloc'd", "realloc'd", "peak live");
+            for phase in Phase::ALL {
+                let a = stats.allocs(phase);
+                eprintln!("{:>12} |{:>10}{:>10}{:>10}{:>14}{:>14}{:>14}{:>14}",
+                    phase.name(),
+                    a.allocations,
+                    a.deallocations,
+                    a.reallocations,
+                    a.bytes_allocated,
+                    a.bytes_deallocated,
+                    a.bytes_reallocated,
+                    a.peak_live_bytes);
+            }
+        };
+
+        self.subs
+            .iter()
+            .for_each(|(sub_idx, stats)| print_sub(&format!("sub {sub_idx}"), stats));
+        print_sub("list", &self.list);
+        print_sub("all", &self.aggregated());
+        eprintln!("(sizes in bytes)");
+    }
+
+    /// Allocation stats are only included with `allocs`.
+    pub(crate) fn print_json(&self, allocs: bool) {
+        let subs = self.subs
+            .iter()
+            .map(|(sub_idx, stats)| (sub_idx.to_string(), stats.to_json(allocs)))
+            .collect::<Map<_, _>>();
+        let report = json!({
+            "subs": subs,
+            "list": self.list.to_json(allocs),
+            "all": self.aggregated().to_json(allocs),
+            "entries": self.entries,
+        });
+        eprintln!("{report:#}");
+    }
+}
@@ -0,0 +1,156 @@
+/*
+    This file is a part of alloc-perf-test.
+
+    Copyright (C) 2024 Mohammad AlSaleh <CE.Mohammad.AlSaleh at gmail.com>
+    https://github.com/MoSal
+
+    alloc-perf-test is free software: you can redistribute it and/or modify
+    it under the terms of the Affero GNU General Public License as
+    published by the Free Software Foundation.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    Affero GNU General Public License for more details.
+
+    You should have received a copy of the Affero GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde::Serialize;
+
+use std::cell::Cell;
+use std::future::Future;
+
+use crate::bench::{self, Stats};
+use crate::{AllocPerfError, AllocPerfRes};
+
+/// `1, 2, 4, ...` up to `max`, with `max` itself always included.
+fn levels(max: usize) -> Vec<usize> {
+    let max = max.max(1);
+    let mut levels = std::iter::successors(Some(1usize), |l| l.checked_mul(2))
+        .take_while(|&l| l < max)
+        .collect::<Vec<_>>();
+    levels.push(max);
+    levels
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ScaleLevel {
+    /// number of concurrent subs and executor threads
+    pub(crate) level: usize,
+    pub(crate) wall: Stats,
+    /// number of list entries produced per run
+    pub(crate) entries: usize,
+    /// entries per second, based on median wall time
+    pub(crate) throughput: f64,
+    /// median wall time of level 1 relative to this level's
+    pub(crate) speedup: f64,
+    /// `speedup / level`
+    pub(crate) efficiency: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ScaleReport {
+    pub(crate) name: String,
+    pub(crate) runs: usize,
+    pub(crate) warmup: usize,
+    pub(crate) levels: Vec<ScaleLevel>,
+}
+
+impl ScaleReport {
+    pub(crate) fn print_human(&self) {
+        let Self { name, runs, warmup, levels } = self;
+        println!("Scaling: {name} ({runs} runs per level, {warmup} warmup)");
+        println!("{:>6} |{:>12}{:>10}{:>12}{:>14}{:>10}{:>12}",
+            "level", "median (s)", "σ (s)", "entries", "entries/s", "speedup", "efficiency");
+        for l in levels {
+            println!("{:>6} |{:>12.3}{:>10.3}{:>12}{:>14.0}{:>10.2}{:>11.1}%",
+                l.level, l.wall.median, l.wall.stddev, l.entries, l.throughput, l.speedup, l.efficiency * 100.0);
+        }
+    }
+
+    pub(crate) fn print_json(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => println!("{json}"),
+            // only possible with non-string map keys, which we don't have
+            Err(e) => tracing::error!("failed to serialize scale report to JSON: {e}"),
+        }
+    }
+}
+
+/// Bench `f(level)` at every level up to `max`, adding executor threads to match
+/// each level before running it.
+///
+/// `f` returns the number of entries produced by a run.
+///
+/// The global executor is expected to be initialized with a single thread, and
+/// `max` max threads. Threads are only added, never stopped, as levels only go up.
+pub(crate) async fn scale<F, Fu>(name: String, max: usize, runs: usize, warmup: usize, mut f: F) -> AllocPerfRes<ScaleReport>
+    where F: FnMut(usize) -> Fu,
+          Fu: Future<Output = AllocPerfRes<usize>>,
+{
+    let mut threads = 1;
+    let mut report_levels = Vec::<ScaleLevel>::new();
+
+    for level in levels(max) {
+        if level > threads {
+            let spawned = async_global_executor::spawn_more_threads(level - threads)
+                .await
+                .map_err(AllocPerfError::SpawnThreads)?;
+            threads += spawned;
+            if threads < level {
+                tracing::warn!("only {threads} executor threads available for level {level}");
+            }
+        }
+
+        tracing::info!("running scale level {level}");
+        let entries = Cell::new(0);
+        let bench_report = bench::bench(format!("{name} level {level}"), runs, warmup, || {
+            let fut = f(level);
+            let entries = &entries;
+            async move {
+                let level_entries = fut.await?;
+                entries.set(level_entries);
+                Ok(level_entries)
+            }
+        }).await?;
+
+        let wall = bench_report.wall;
+        let entries = entries.get();
+        let base_median = report_levels.first().map_or(wall.median, |l| l.wall.median);
+        let speedup = base_median / wall.median;
+        report_levels.push(ScaleLevel {
+            level,
+            entries,
+            throughput: entries as f64 / wall.median,
+            speedup,
+            efficiency: speedup / level as f64,
+            wall,
+        });
+    }
+
+    Ok(ScaleReport { name, runs, warmup, levels: report_levels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_bounds() {
+        assert_eq!(levels(0), [1]);
+        assert_eq!(levels(1), [1]);
+        assert_eq!(levels(2), [1, 2]);
+        assert_eq!(levels(6), [1, 2, 4, 6]);
+        assert_eq!(levels(8), [1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn level_bounds_max_usize() {
+        let levels = levels(usize::MAX);
+        assert_eq!(levels.len(), usize::BITS as usize + 1);
+        assert_eq!(levels[levels.len() - 2], 1 << (usize::BITS - 1));
+        assert_eq!(levels.last(), Some(&usize::MAX));
+    }
+}
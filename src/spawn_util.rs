@@ -18,9 +18,15 @@
 */
 
 use async_global_executor as a_exec;
+use futures_lite::future;
+
 use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+
+use crate::cli::Scheduler;
 
-async fn chunk_run<const CHUNK_SZ: usize, const RETRIES: usize, T, I, R, E, Fu, F>(chunk_iter: I, f: F) -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
+async fn chunk_run<const RETRIES: usize, T, I, R, E, Fu, F>(chunk_sz: usize, chunk_iter: I, f: F) -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
     where I: Iterator<Item = T>,
           T: Send + Clone + std::fmt::Debug + 'static,
           Fu: Future<Output = Result<R, E>> + Send + 'static,
@@ -28,8 +34,8 @@ async fn chunk_run<const CHUNK_SZ: usize, const RETRIES: usize, T, I, R, E, Fu,
           R: Send + 'static,
           E: Into<crate::AllocPerfError> + Send + std::fmt::Display + 'static,
 {
-    let mut chunk_ret_vec = Vec::with_capacity(CHUNK_SZ);
-    let mut errors = Vec::with_capacity((CHUNK_SZ/2).max(1));
+    let mut chunk_ret_vec = Vec::with_capacity(chunk_sz);
+    let mut errors = Vec::with_capacity((chunk_sz/2).max(1));
 
     let tasks_results = chunk_iter
         .map(|t| (t.clone(), a_exec::spawn(f(t))))
@@ -80,10 +86,9 @@ async fn chunk_run<const CHUNK_SZ: usize, const RETRIES: usize, T, I, R, E, Fu,
 }
 
 pub(crate) async fn chunked_spawn_runner_with_retries<
-    const CHUNK_SZ: usize,
     const RETRIES: usize,
     const ALLOW_ERR: bool,
-    T, I, R, E, Fu, F>(full_iter: I, f: F) -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
+    T, I, R, E, Fu, F>(chunk_sz: usize, full_iter: I, f: F) -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
     where I: Iterator<Item = T>,
           T: Send + Clone + std::fmt::Debug + 'static,
           Fu: Future<Output = Result<R, E>> + Send + 'static,
@@ -91,35 +96,116 @@ pub(crate) async fn chunked_spawn_runner_with_retries<
           R: Send + 'static,
           E: Into<crate::AllocPerfError> + Send + std::fmt::Display + 'static,
 {
-    let mut ret_vec = Vec::with_capacity(full_iter.size_hint().1.unwrap_or(CHUNK_SZ*2).max(1));
-    let mut chunks = full_iter.array_chunks::<CHUNK_SZ>();
-    let mut errors = Vec::with_capacity(CHUNK_SZ*2);
-
-    let chunk_run = chunk_run::<CHUNK_SZ, RETRIES, _, _, _, _, _, _>;
-
-    macro_rules! get_chunk {
-        ($chunk_iter:expr) => {
-            match chunk_run($chunk_iter, f).await {
-                Ok(chunk_ret_vec) => ret_vec.extend(chunk_ret_vec),
-                Err((partial_chunk_ret_vec, e)) => {
-                    ret_vec.extend(partial_chunk_ret_vec);
-                    errors.extend(e);
-                    if !ALLOW_ERR {
-                        return Err((ret_vec, errors));
-                    }
-                },
-            }
-        };
+    // a zero chunk size would never make progress
+    let chunk_sz = chunk_sz.max(1);
+    let mut ret_vec = Vec::with_capacity(full_iter.size_hint().1.unwrap_or(chunk_sz*2).max(1));
+    let mut full_iter = full_iter.peekable();
+    let mut errors = Vec::with_capacity(chunk_sz*2);
+
+    while full_iter.peek().is_some() {
+        match chunk_run::<RETRIES, _, _, _, _, _, _>(chunk_sz, full_iter.by_ref().take(chunk_sz), f).await {
+            Ok(chunk_ret_vec) => ret_vec.extend(chunk_ret_vec),
+            Err((partial_chunk_ret_vec, e)) => {
+                ret_vec.extend(partial_chunk_ret_vec);
+                errors.extend(e);
+                if !ALLOW_ERR {
+                    return Err((ret_vec, errors));
+                }
+            },
+        }
     }
 
-    while let Some(spawn_control_chunk) = chunks.next() {
-        get_chunk!(spawn_control_chunk.into_iter());
+    if errors.is_empty() {
+        Ok(ret_vec)
+    } else {
+        Err((ret_vec, errors))
     }
+}
+
+pub(crate) async fn chunked_spawn_runner<
+    const ALLOW_ERR: bool,
+    T, I, R, E, Fu, F>(chunk_sz: usize, full_iter: I, f: F)
+    -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
+    where I: Iterator<Item = T>,
+          T: Send + Clone + std::fmt::Debug + 'static,
+          Fu: Future<Output = Result<R, E>> + Send + 'static,
+          F: Fn(T) -> Fu + Copy,
+          R: Send + 'static,
+          E: Into<crate::AllocPerfError> + Send + std::fmt::Display + 'static,
+{
+    chunked_spawn_runner_with_retries::<0, ALLOW_ERR, _, _, _, _, _, _>(chunk_sz, full_iter, f).await
+}
+
+struct WindowSlot<T, R, E> {
+    idx: usize,
+    item: T,
+    try_num: usize,
+    task: a_exec::Task<Result<R, E>>,
+}
+
+/// Like `chunked_spawn_runner_with_retries()`, but instead of waiting for a whole chunk
+/// to finish, a new task is started as soon as a running one finishes. So `window_sz`
+/// tasks are kept in flight at all times (until items run out).
+///
+/// Retries take the failed task's place in the window. Results are returned in input order.
+/// Without `ALLOW_ERR`, no new tasks are started after the first error, but tasks already
+/// in flight are still awaited.
+pub(crate) async fn window_spawn_runner_with_retries<
+    const RETRIES: usize,
+    const ALLOW_ERR: bool,
+    T, I, R, E, Fu, F>(window_sz: usize, full_iter: I, f: F) -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
+    where I: Iterator<Item = T>,
+          T: Send + Clone + std::fmt::Debug + 'static,
+          Fu: Future<Output = Result<R, E>> + Send + 'static,
+          F: Fn(T) -> Fu + Copy,
+          R: Send + 'static,
+          E: Into<crate::AllocPerfError> + Send + std::fmt::Display + 'static,
+{
+    let window_sz = window_sz.max(1);
+    let tries = RETRIES + 1;
+    let mut ret_opts = Vec::with_capacity(full_iter.size_hint().1.unwrap_or(window_sz*2).max(1));
+    let mut full_iter = full_iter.enumerate();
+    let mut errors = Vec::with_capacity(window_sz*2);
+    let mut in_flight = Vec::<WindowSlot<T, R, E>>::with_capacity(window_sz);
+
+    loop {
+        if ALLOW_ERR || errors.is_empty() {
+            while in_flight.len() < window_sz && let Some((idx, item)) = full_iter.next() {
+                ret_opts.push(None);
+                let task = a_exec::spawn(f(item.clone()));
+                in_flight.push(WindowSlot { idx, item, try_num: 1, task });
+            }
+        }
 
-    if let Some (spawn_control_rem_iter) = chunks.into_remainder() {
-        get_chunk!(spawn_control_rem_iter);
+        if in_flight.is_empty() {
+            break;
+        }
+
+        let (slot_pos, res) = future::poll_fn(|cx| {
+            in_flight
+                .iter_mut()
+                .enumerate()
+                .find_map(|(pos, slot)| match Pin::new(&mut slot.task).poll(cx) {
+                    Poll::Ready(res) => Some(Poll::Ready((pos, res))),
+                    Poll::Pending => None,
+                })
+                .unwrap_or(Poll::Pending)
+        }).await;
+
+        let WindowSlot { idx, item, try_num, .. } = in_flight.swap_remove(slot_pos);
+        match res {
+            Ok(v) => ret_opts[idx] = Some(v),
+            Err(e) if try_num < tries => {
+                tracing::warn!("try {try_num}/{tries} failed with error: {e}");
+                tracing::warn!("start try {}/{tries} task for item {item:?}", try_num + 1);
+                let task = a_exec::spawn(f(item.clone()));
+                in_flight.push(WindowSlot { idx, item, try_num: try_num + 1, task });
+            },
+            Err(e) => errors.push(e.into()),
+        }
     }
 
+    let ret_vec = ret_opts.into_iter().flatten().collect();
     if errors.is_empty() {
         Ok(ret_vec)
     } else {
@@ -127,10 +213,24 @@ pub(crate) async fn chunked_spawn_runner_with_retries<
     }
 }
 
-pub(crate) async fn chunked_spawn_runner<
-    const CHUNK_SZ: usize,
+pub(crate) async fn window_spawn_runner<
+    const ALLOW_ERR: bool,
+    T, I, R, E, Fu, F>(window_sz: usize, full_iter: I, f: F)
+    -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
+    where I: Iterator<Item = T>,
+          T: Send + Clone + std::fmt::Debug + 'static,
+          Fu: Future<Output = Result<R, E>> + Send + 'static,
+          F: Fn(T) -> Fu + Copy,
+          R: Send + 'static,
+          E: Into<crate::AllocPerfError> + Send + std::fmt::Display + 'static,
+{
+    window_spawn_runner_with_retries::<0, ALLOW_ERR, _, _, _, _, _, _>(window_sz, full_iter, f).await
+}
+
+/// Run with the runner matching `scheduler`, with `jobs` as chunk or window size.
+pub(crate) async fn spawn_runner<
     const ALLOW_ERR: bool,
-    T, I, R, E, Fu, F>(full_iter: I, f: F)
+    T, I, R, E, Fu, F>(scheduler: Scheduler, jobs: usize, full_iter: I, f: F)
     -> Result<Vec<R>, (Vec<R>, Vec<crate::AllocPerfError>)>
     where I: Iterator<Item = T>,
           T: Send + Clone + std::fmt::Debug + 'static,
@@ -139,5 +239,90 @@ pub(crate) async fn chunked_spawn_runner<
           R: Send + 'static,
           E: Into<crate::AllocPerfError> + Send + std::fmt::Display + 'static,
 {
-    chunked_spawn_runner_with_retries::<CHUNK_SZ, 0, ALLOW_ERR, _, _, _, _, _, _>(full_iter, f).await
+    match scheduler {
+        Scheduler::Chunked => chunked_spawn_runner::<ALLOW_ERR, _, _, _, _, _, _>(jobs, full_iter, f).await,
+        Scheduler::Window => window_spawn_runner::<ALLOW_ERR, _, _, _, _, _, _>(jobs, full_iter, f).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::AllocPerfError;
+
+    fn err(item: usize) -> AllocPerfError {
+        AllocPerfError::SpawnThreads(std::io::Error::other(format!("item {item} failed")))
+    }
+
+    async fn sleep_ms(ms: u64) {
+        blocking::unblock(move || std::thread::sleep(Duration::from_millis(ms))).await
+    }
+
+    #[test]
+    fn window_retries_replace_failed_tasks() {
+        static TRIES: [AtomicUsize; 6] = [const { AtomicUsize::new(0) }; 6];
+
+        let f = |item: usize| async move {
+            let try_num = TRIES[item].fetch_add(1, Ordering::SeqCst) + 1;
+            match item {
+                // fails once, then succeeds
+                1 if try_num == 1 => Err(err(item)),
+                // fails every try
+                4 => Err(err(item)),
+                _ => Ok(item),
+            }
+        };
+        let res = block_on(window_spawn_runner_with_retries::<2, true, _, _, _, _, _, _>(2, 0..6, f));
+
+        let (ret_vec, errors) = res.unwrap_err();
+        assert_eq!(ret_vec, [0, 1, 2, 3, 5]);
+        assert_eq!(errors.len(), 1);
+        let tries = TRIES.iter().map(|t| t.load(Ordering::SeqCst)).collect::<Vec<_>>();
+        assert_eq!(tries, [1, 2, 1, 1, 3, 1]);
+    }
+
+    #[test]
+    fn window_results_in_input_order() {
+        static DONE: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        // all items are in flight at once, and later items finish first
+        let res = block_on(window_spawn_runner::<false, _, _, _, _, _, _>(4, 0..4, |item: usize| async move {
+            sleep_ms(50 * (4 - item as u64)).await;
+            DONE.lock().unwrap().push(item);
+            Ok::<_, AllocPerfError>(item * 10)
+        }));
+
+        assert_eq!(res.unwrap(), [0, 10, 20, 30]);
+        assert_eq!(*DONE.lock().unwrap(), [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn window_stops_spawning_after_error() {
+        static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+        let res = block_on(window_spawn_runner::<false, _, _, _, _, _, _>(2, 0..10, |item: usize| {
+            SPAWNED.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match item {
+                    0 => Err(err(item)),
+                    _ => {
+                        sleep_ms(50).await;
+                        Ok(item)
+                    },
+                }
+            }
+        }));
+
+        // the task already in flight when item 0 failed is still awaited
+        let (ret_vec, errors) = res.unwrap_err();
+        assert_eq!(ret_vec, [1]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(SPAWNED.load(Ordering::SeqCst), 2);
+    }
 }
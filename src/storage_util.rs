@@ -17,15 +17,21 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use lz4_flex::frame::{FrameEncoder, FrameDecoder};
 use speedy::{Readable, Writable, Endianness};
 use thiserror::Error;
 
-use std::io::{Cursor, Read, Write};
+use std::hash::Hasher;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 
+use crate::cli::{Codec, DataEndianness, LoadMode};
 use crate::conf::SubFull;
+use crate::fs_util::FsUtilError;
 use crate::fs_util::file::{UpdatableWritableFile, UpdatedOrRolledBack, ExistentReadableFile};
+use crate::phase_util::{Phase, PhaseStats};
 
 use crate::AllocPerfRes;
 
@@ -35,12 +41,55 @@ pub enum StorageUtilError {
     SpeedyReadFailed(#[source] speedy::Error),
     #[error("failed to speedy-serialize to bytes: {0}")]
     SpeedyWriteFailed(#[source] speedy::Error),
-    #[error("failed to decompress supposedly brotli-compressed bytes: {0}")]
-    Decompress(#[source] std::io::Error),
-    #[error("failed to brotli-compress bytes: {0}")]
-    Compress(#[source] std::io::Error),
+    #[error("failed to decompress supposedly {codec:?}-compressed bytes: {source}")]
+    Decompress {
+        codec: Codec,
+        source: std::io::Error,
+    },
+    #[error("failed to decompress supposedly lz4-block-compressed bytes: {0}")]
+    Lz4BlockDecompress(#[source] lz4_flex::block::DecompressError),
+    #[error("header says {uncompressed_len} bytes are lz4-block-compressed into {compressed_len} bytes, which is not possible, file is probably corrupted")]
+    Lz4BlockLenImpossible {
+        uncompressed_len: u64,
+        compressed_len: usize,
+    },
+    #[error("failed to {codec:?}-compress bytes: {source}")]
+    Compress {
+        codec: Codec,
+        source: std::io::Error,
+    },
     #[error("failed to finish lz4 compression: {0}")]
     CompressFinish(#[source] lz4_flex::frame::Error),
+    #[error("invalid codec value '{0}' in storage header")]
+    InvalidCodec(u8),
+    #[error("no storage header found, file was probably written by an older version (re-run gen-data)")]
+    MissingHeader,
+    #[error("storage header is truncated")]
+    TruncatedHeader,
+    #[error("unsupported storage format version {found} (supported version: {supported}), re-run gen-data")]
+    UnsupportedVersion {
+        found: u16,
+        supported: u16,
+    },
+    #[error("stored data is of type '{found}', expected '{expected}'")]
+    TypeMismatch {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("invalid endianness value '{0}' in storage header")]
+    InvalidEndianness(u8),
+    #[error("uncompressed data is {found} bytes, header says {expected} bytes")]
+    UncompressedLenMismatch {
+        expected: u64,
+        found: u64,
+    },
+    #[error("checksum of uncompressed data is {found:#018x}, header says {expected:#018x}, file is probably corrupted or truncated")]
+    ChecksumMismatch {
+        expected: u64,
+        found: u64,
+    },
+    #[error("failed reading stored data stream: {0}")]
+    StreamReadFailed(#[source] std::io::Error),
 }
 
 pub(crate) trait StorageInfo<const HAS_PARAM: bool = false> {
@@ -48,7 +97,244 @@ pub(crate) trait StorageInfo<const HAS_PARAM: bool = false> {
 
 }
 
-const ENDIANNESS: Endianness = Endianness::NATIVE;
+/// An upper bound on the lz4 compression ratio (extending a match by 255 bytes costs a byte).
+const LZ4_MAX_RATIO: u64 = 255;
+
+/// Endianness of data written before storage headers were added.
+const LEGACY_ENDIANNESS: Endianness = Endianness::NATIVE;
+
+impl From<DataEndianness> for Endianness {
+    fn from(endianness: DataEndianness) -> Self {
+        match endianness {
+            DataEndianness::Little => Self::LittleEndian,
+            DataEndianness::Big => Self::BigEndian,
+        }
+    }
+}
+
+/// How data files are written.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WriteOpts {
+    pub(crate) codec: Codec,
+    pub(crate) endianness: DataEndianness,
+}
+
+/// Header written before the compressed payload of every binz file.
+///
+/// Layout (integers are little endian):
+/// `MAGIC`, version (u16), type id length (u8), type id, endianness (u8), codec (u8),
+/// uncompressed length (u64), XXH64 checksum of uncompressed data (u64).
+#[derive(Debug)]
+struct StorageHeader {
+    version: u16,
+    type_id: String,
+    endianness: Endianness,
+    codec: Codec,
+    uncompressed_len: u64,
+    checksum: u64,
+}
+
+impl StorageHeader {
+    const MAGIC: &'static [u8; 8] = b"APTBINZ\0";
+    const VERSION: u16 = 3;
+
+    fn new(type_id: &'static str, endianness: Endianness, codec: Codec, serialized: &[u8]) -> Self {
+        Self {
+            version: Self::VERSION,
+            type_id: type_id.to_owned(),
+            endianness,
+            codec,
+            uncompressed_len: serialized.len() as u64,
+            checksum: Self::checksum(serialized),
+        }
+    }
+
+    fn checksum(bytes: &[u8]) -> u64 {
+        let mut hasher = twox_hash::XxHash64::with_seed(0);
+        hasher.write(bytes);
+        hasher.finish()
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        // type ids are file names, and are all short
+        let type_id = &self.type_id.as_bytes()[..self.type_id.len().min(u8::MAX as usize)];
+        let endianness = match self.endianness {
+            Endianness::LittleEndian => 0u8,
+            Endianness::BigEndian => 1u8,
+        };
+        out.extend_from_slice(Self::MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.push(type_id.len() as u8);
+        out.extend_from_slice(type_id);
+        out.push(endianness);
+        out.push(self.codec as u8);
+        out.extend_from_slice(&self.uncompressed_len.to_le_bytes());
+        out.extend_from_slice(&self.checksum.to_le_bytes());
+    }
+
+    /// Returns the header, and the rest of `bytes` after it.
+    fn read_from(bytes: &[u8]) -> Result<(Self, &[u8]), StorageUtilError> {
+        fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], StorageUtilError> {
+            let (taken, rest) = bytes.split_first_chunk::<N>()
+                .ok_or(StorageUtilError::TruncatedHeader)?;
+            *bytes = rest;
+            Ok(*taken)
+        }
+
+        let mut bytes = bytes;
+        match bytes.strip_prefix(Self::MAGIC) {
+            Some(rest) => bytes = rest,
+            None => return Err(StorageUtilError::MissingHeader),
+        }
+
+        let version = u16::from_le_bytes(take(&mut bytes)?);
+        if version != Self::VERSION {
+            return Err(StorageUtilError::UnsupportedVersion { found: version, supported: Self::VERSION });
+        }
+
+        let [type_id_len] = take(&mut bytes)?;
+        let (type_id, rest) = bytes.split_at_checked(type_id_len as usize)
+            .ok_or(StorageUtilError::TruncatedHeader)?;
+        let type_id = String::from_utf8_lossy(type_id).into_owned();
+        bytes = rest;
+
+        let endianness = match take(&mut bytes)? {
+            [0] => Endianness::LittleEndian,
+            [1] => Endianness::BigEndian,
+            [e] => return Err(StorageUtilError::InvalidEndianness(e)),
+        };
+        let codec = match take(&mut bytes)? {
+            [c] if c == Codec::None as u8 => Codec::None,
+            [c] if c == Codec::Lz4Frame as u8 => Codec::Lz4Frame,
+            [c] if c == Codec::Lz4Block as u8 => Codec::Lz4Block,
+            [c] if c == Codec::Deflate as u8 => Codec::Deflate,
+            [c] => return Err(StorageUtilError::InvalidCodec(c)),
+        };
+        let uncompressed_len = u64::from_le_bytes(take(&mut bytes)?);
+        let checksum = u64::from_le_bytes(take(&mut bytes)?);
+
+        Ok((Self { version, type_id, endianness, codec, uncompressed_len, checksum }, bytes))
+    }
+
+    /// Like `read_from()`, reading exactly the header bytes from `r`.
+    fn read_from_stream(r: &mut impl Read) -> Result<Self, StorageUtilError> {
+        fn read_exact(r: &mut impl Read, buf: &mut [u8]) -> Result<(), StorageUtilError> {
+            r.read_exact(buf).map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => StorageUtilError::TruncatedHeader,
+                _ => StorageUtilError::StreamReadFailed(e),
+            })
+        }
+
+        // magic, version and type id length, which gives the length of the rest
+        let prefix_len = Self::MAGIC.len() + 3;
+        let mut bytes = vec![0u8; prefix_len];
+        read_exact(r, &mut bytes).map_err(|e| match e {
+            StorageUtilError::TruncatedHeader => StorageUtilError::MissingHeader,
+            e => e,
+        })?;
+        if !bytes.starts_with(Self::MAGIC) {
+            return Err(StorageUtilError::MissingHeader);
+        }
+
+        // type id, endianness, codec, uncompressed length and checksum
+        let rest_len = bytes[prefix_len - 1] as usize + 1 + 1 + 8 + 8;
+        bytes.resize(prefix_len + rest_len, 0);
+        read_exact(r, &mut bytes[prefix_len..])?;
+
+        let (header, _) = Self::read_from(&bytes)?;
+        Ok(header)
+    }
+
+    fn validate_type(&self, expected: &'static str) -> Result<(), StorageUtilError> {
+        match self.type_id == expected {
+            true => Ok(()),
+            false => Err(StorageUtilError::TypeMismatch { expected, found: self.type_id.clone() }),
+        }
+    }
+
+    fn validate_payload(&self, serialized: &[u8]) -> Result<(), StorageUtilError> {
+        self.validate_digest(serialized.len() as u64, Self::checksum(serialized))
+    }
+
+    fn validate_digest(&self, len: u64, checksum: u64) -> Result<(), StorageUtilError> {
+        if len != self.uncompressed_len {
+            return Err(StorageUtilError::UncompressedLenMismatch {
+                expected: self.uncompressed_len,
+                found: len,
+            });
+        }
+        if checksum != self.checksum {
+            return Err(StorageUtilError::ChecksumMismatch { expected: self.checksum, found: checksum });
+        }
+        Ok(())
+    }
+}
+
+/// Counts and hashes all bytes read through it, so streamed payloads can be validated
+/// against their header without being buffered.
+struct DigestReader<R> {
+    inner: R,
+    hasher: twox_hash::XxHash64,
+    len: u64,
+}
+
+impl<R: Read> DigestReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: twox_hash::XxHash64::with_seed(0), len: 0 }
+    }
+
+    /// Reads what's left of the stream, and returns the length and checksum of all of it.
+    fn finish(mut self) -> std::io::Result<(u64, u64)> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+        Ok((self.len, self.hasher.finish()))
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+}
+
+/// Storage header of a binz file, and the file's size.
+#[derive(Debug)]
+pub(crate) struct BinzFileInfo {
+    pub(crate) type_id: String,
+    pub(crate) version: u16,
+    pub(crate) endianness: Endianness,
+    pub(crate) codec: Codec,
+    pub(crate) uncompressed_len: u64,
+    pub(crate) checksum: u64,
+    pub(crate) file_len: u64,
+}
+
+impl BinzFileInfo {
+    /// Only reads the header, data is not loaded or validated.
+    pub(crate) async fn from_binz_file(path: impl AsRef<Path>) -> AllocPerfRes<Self> {
+        let path = path.as_ref().to_owned();
+        blocking::unblock(move || {
+            let file = ExistentReadableFile::open_blocking(&path)?;
+            let file_len = file.metadata()
+                .map_err(|source| FsUtilError::UnexpectedErrorGettingMetaInfo{
+                    path: path.clone(),
+                    source,
+                })?
+                .len();
+            let StorageHeader { version, type_id, endianness, codec, uncompressed_len, checksum } =
+                StorageHeader::read_from_stream(&mut BufReader::new(file))?;
+            Ok(Self { type_id, version, endianness, codec, uncompressed_len, checksum, file_len })
+        }).await
+    }
+
+    /// Whether `val` serializes to the exact data this header was written for (same length and checksum).
+    pub(crate) fn matches_blocking<T: StorageOpsSpeedy>(&self, val: &T) -> AllocPerfRes<bool> {
+        let serialized = val.to_serialized_blocking(self.endianness)?;
+        Ok(serialized.len() as u64 == self.uncompressed_len && StorageHeader::checksum(&serialized) == self.checksum)
+    }
+}
 
 pub(crate) trait IsSpeedyRwRd:
     Writable<Endianness> +
@@ -58,48 +344,272 @@ pub(crate) trait IsSpeedyRwRd:
     const FILE_NAME: &'static str;
 }
 
+/// Stored types that can also be deserialized as views borrowing their strings from serialized data.
+pub(crate) trait IsSpeedyView: IsSpeedyRwRd {
+    type View<'a>: Readable<'a, Endianness>;
+}
+
+/// A mapped binz file of a `T` with a validated payload, which is decompressed into a buffer if compressed.
+pub(crate) struct MappedBinz<T> {
+    header: StorageHeader,
+    mapped: memmap2::Mmap,
+    payload_offset: usize,
+    decompressed: Option<Vec<u8>>,
+    _type: PhantomData<T>,
+}
+
+impl<T: StorageOpsSpeedy> MappedBinz<T> {
+    fn new_blocking(mapped: memmap2::Mmap) -> AllocPerfRes<Self> {
+        let (header, compressed) = StorageHeader::read_from(&mapped)?;
+        header.validate_type(T::FILE_NAME)?;
+        let payload_offset = mapped.len() - compressed.len();
+
+        let decompressed = match header.codec {
+            Codec::None => None,
+            _ => Some(T::decompress_blocking(&header, compressed)?),
+        };
+
+        let mapped_binz = Self { header, mapped, payload_offset, decompressed, _type: PhantomData };
+        mapped_binz.header.validate_payload(mapped_binz.payload())?;
+        Ok(mapped_binz)
+    }
+
+    /// Uncompressed payload, borrowed from the mapping if the file is not compressed.
+    fn payload(&self) -> &[u8] {
+        match &self.decompressed {
+            Some(decompressed) => decompressed,
+            None => &self.mapped[self.payload_offset..],
+        }
+    }
+}
+
+impl<T: StorageOpsSpeedy + IsSpeedyView> MappedBinz<T> {
+    /// Deserialize a view of the data, with strings borrowed from the payload instead of allocated.
+    ///
+    /// Maps, sets and vectors in the data are still allocated.
+    pub(crate) fn view(&self, stats: &mut PhaseStats) -> AllocPerfRes<T::View<'_>> {
+        let view = stats.record(Phase::Deserialize, || T::View::read_from_buffer_with_ctx(self.header.endianness, self.payload()))
+            .map_err(StorageUtilError::SpeedyReadFailed)?;
+        Ok(view)
+    }
+}
+
 trait StoragePrivSpeedy: StorageInfo + IsSpeedyRwRd {
-    fn serialized_from_binz_blocking(binz: Vec<u8>) -> AllocPerfRes<Vec<u8>> {
-        let mut decomp = FrameDecoder::new(&*binz);
-        let mut serialized = Vec::with_capacity(binz.len() * 4);
-        decomp.read_to_end(&mut serialized)
-            .map_err(|source| StorageUtilError::Decompress(source))?;
+    fn decompress_blocking(header: &StorageHeader, compressed: &[u8]) -> AllocPerfRes<Vec<u8>> {
+        let codec = header.codec;
+        let serialized = match codec {
+            Codec::None => compressed.to_vec(),
+            Codec::Lz4Frame => {
+                let mut decomp = FrameDecoder::new(compressed);
+                let mut serialized = Vec::with_capacity(compressed.len() * 4);
+                decomp.read_to_end(&mut serialized)
+                    .map_err(|source| StorageUtilError::Decompress { codec, source })?;
+                serialized
+            },
+            Codec::Lz4Block => {
+                // the buffer is allocated upfront with the length from the header, which is not validated yet
+                if header.uncompressed_len > (compressed.len() as u64).saturating_mul(LZ4_MAX_RATIO) {
+                    Err(StorageUtilError::Lz4BlockLenImpossible {
+                        uncompressed_len: header.uncompressed_len,
+                        compressed_len: compressed.len(),
+                    })?;
+                }
+                let uncompressed_len = usize::try_from(header.uncompressed_len)
+                    .map_err(|_| FsUtilError::UsizeFromU64(header.uncompressed_len))?;
+                lz4_flex::block::decompress(compressed, uncompressed_len)
+                    .map_err(StorageUtilError::Lz4BlockDecompress)?
+            },
+            Codec::Deflate => {
+                let mut decomp = DeflateDecoder::new(compressed);
+                let mut serialized = Vec::with_capacity(compressed.len() * 4);
+                decomp.read_to_end(&mut serialized)
+                    .map_err(|source| StorageUtilError::Decompress { codec, source })?;
+                serialized
+            },
+        };
         Ok(serialized)
     }
 
-    fn from_serialized_blocking(serialized: Vec<u8>) -> AllocPerfRes<Self> {
+    fn serialized_from_binz_blocking(mut binz: Vec<u8>) -> AllocPerfRes<(StorageHeader, Vec<u8>)> {
+        let (header, compressed) = StorageHeader::read_from(&binz)?;
+        let header_len = binz.len() - compressed.len();
+        header.validate_type(Self::FILE_NAME)?;
+
+        let serialized = match header.codec {
+            Codec::None => {
+                // no new buffer, but the payload is still moved (memmove) to the start of this one
+                binz.drain(..header_len);
+                binz
+            },
+            _ => Self::decompress_blocking(&header, &binz[header_len..])?,
+        };
+
+        header.validate_payload(&serialized)?;
+        Ok((header, serialized))
+    }
+
+    fn from_serialized_blocking(serialized: Vec<u8>, endianness: Endianness) -> AllocPerfRes<Self> {
         let serialized_s = Cursor::new(serialized);
 
-        let val = Self::read_from_stream_buffered_with_ctx(ENDIANNESS, serialized_s)
-            .map_err(|source| StorageUtilError::SpeedyReadFailed(source))?;
+        let val = Self::read_from_stream_buffered_with_ctx(endianness, serialized_s)
+            .map_err(StorageUtilError::SpeedyReadFailed)?;
 
         Ok(val)
     }
 
-    async fn from_binz(binz: Vec<u8>) -> AllocPerfRes<Self> {
-        blocking::unblock(move || {
-            let serialized = Self::serialized_from_binz_blocking(binz)?;
-            Self::from_serialized_blocking(serialized)
-        }).await
+    /// Deserializes straight from `serialized`, without going through a buffered stream.
+    ///
+    /// Owned values are deserialized, so strings are still allocated (see `MappedBinz::view()` for that).
+    /// But no intermediate copies of `serialized` are made.
+    fn from_serialized_slice_blocking(serialized: &[u8], endianness: Endianness) -> AllocPerfRes<Self> {
+        let val = Self::read_from_buffer_with_ctx(endianness, serialized)
+            .map_err(StorageUtilError::SpeedyReadFailed)?;
+        Ok(val)
+    }
+
+    async fn from_binz(binz: Vec<u8>, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
+        let (val_res, unblocked_stats) = blocking::unblock(move || {
+            let mut stats = PhaseStats::default();
+            let val_res = try {
+                let (header, serialized) = stats.record(Phase::Decompress, || Self::serialized_from_binz_blocking(binz))?;
+                stats.record(Phase::Deserialize, || Self::from_serialized_blocking(serialized, header.endianness))?
+            };
+            (val_res, stats)
+        }).await;
+        stats.merge(&unblocked_stats);
+        val_res
+    }
+
+    async fn from_mapped(mapped: memmap2::Mmap, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
+        let (val_res, unblocked_stats) = blocking::unblock(move || {
+            let mut stats = PhaseStats::default();
+            let val_res = try {
+                let mapped_binz = stats.record(Phase::Decompress, || MappedBinz::<Self>::new_blocking(mapped))?;
+                let val = stats.record(Phase::Deserialize, || Self::from_serialized_slice_blocking(mapped_binz.payload(), mapped_binz.header.endianness))?;
+                stats.record(Phase::Teardown, || drop(mapped_binz));
+                val
+            };
+            (val_res, stats)
+        }).await;
+        stats.merge(&unblocked_stats);
+        val_res
     }
 
-    fn to_serialized_blocking(&self) -> AllocPerfRes<Vec<u8>> {
-        let bytes = self.write_to_vec_with_ctx(ENDIANNESS)
-            .map_err(|source| StorageUtilError::SpeedyWriteFailed(source))?;
+    async fn map_binz_file(path: PathBuf, stats: &mut PhaseStats) -> AllocPerfRes<MappedBinz<Self>> {
+        // pages are only read when touched, so most of the reading is recorded in later phases
+        let mapped = stats.record_async(Phase::Read, async {
+            ExistentReadableFile::open(path)
+                .await?
+                .mmap()
+        }).await?;
+
+        let (mapped_res, unblocked_stats) = blocking::unblock(move || {
+            let mut stats = PhaseStats::default();
+            let mapped_res = stats.record(Phase::Decompress, || MappedBinz::new_blocking(mapped));
+            (mapped_res, stats)
+        }).await;
+        stats.merge(&unblocked_stats);
+        mapped_res
+    }
+
+    /// Deserializes from `serialized_r` as it's read, then validates everything read against `header`.
+    ///
+    /// So unlike other load paths, corrupted data is only detected after (or while) deserializing it.
+    fn from_serialized_stream_blocking(header: &StorageHeader, serialized_r: impl Read) -> AllocPerfRes<Self> {
+        let mut digest_r = DigestReader::new(serialized_r);
+        let val = Self::read_from_stream_buffered_with_ctx(header.endianness, &mut digest_r)
+            .map_err(StorageUtilError::SpeedyReadFailed)?;
+        let (len, checksum) = digest_r.finish()
+            .map_err(StorageUtilError::StreamReadFailed)?;
+        header.validate_digest(len, checksum)?;
+        Ok(val)
+    }
+
+    async fn from_binz_file_streamed(path: PathBuf, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
+        let (val_res, unblocked_stats) = blocking::unblock(move || {
+            let mut stats = PhaseStats::default();
+            let val_res = try {
+                let (header, mut binz_r) = stats.record(Phase::Read, || -> AllocPerfRes<_> {
+                    let mut binz_r = BufReader::new(ExistentReadableFile::open_blocking(&path)?);
+                    let header = StorageHeader::read_from_stream(&mut binz_r)?;
+                    header.validate_type(Self::FILE_NAME)?;
+                    Ok((header, binz_r))
+                })?;
+
+                // reading, decompression and deserialization are interleaved, so all of it is recorded as deserialization
+                match header.codec {
+                    Codec::None => stats.record(Phase::Deserialize, || Self::from_serialized_stream_blocking(&header, binz_r))?,
+                    Codec::Lz4Frame => stats.record(Phase::Deserialize, || Self::from_serialized_stream_blocking(&header, FrameDecoder::new(binz_r)))?,
+                    Codec::Deflate => stats.record(Phase::Deserialize, || Self::from_serialized_stream_blocking(&header, DeflateDecoder::new(binz_r)))?,
+                    Codec::Lz4Block => {
+                        // the block format can't be decompressed incrementally
+                        tracing::debug!("{desc} @ '{path_str}' is lz4-block-compressed, loading it whole",
+                            desc=Self::DESC,
+                            path_str=path.to_string_lossy());
+                        let mut compressed = Vec::new();
+                        stats.record(Phase::Read, || -> AllocPerfRes<_> {
+                            binz_r.read_to_end(&mut compressed)
+                                .map_err(|source| FsUtilError::ExistentReadableFileReadFailed{ path, source })?;
+                            Ok(())
+                        })?;
+                        let serialized = stats.record(Phase::Decompress, || -> AllocPerfRes<_> {
+                            let serialized = Self::decompress_blocking(&header, &compressed)?;
+                            header.validate_payload(&serialized)?;
+                            Ok(serialized)
+                        })?;
+                        stats.record(Phase::Deserialize, || Self::from_serialized_slice_blocking(&serialized, header.endianness))?
+                    },
+                }
+            };
+            (val_res, stats)
+        }).await;
+        stats.merge(&unblocked_stats);
+        val_res
+    }
+
+    /// For files written before storage headers were added, which are lz4 frames
+    /// of natively-encoded data.
+    fn from_legacy_binz_blocking(binz: Vec<u8>) -> AllocPerfRes<Self> {
+        let codec = Codec::Lz4Frame;
+        let mut decomp = FrameDecoder::new(&*binz);
+        let mut serialized = Vec::with_capacity(binz.len() * 4);
+        decomp.read_to_end(&mut serialized)
+            .map_err(|source| StorageUtilError::Decompress { codec, source })?;
+        Self::from_serialized_blocking(serialized, LEGACY_ENDIANNESS)
+    }
+
+    fn to_serialized_blocking(&self, endianness: Endianness) -> AllocPerfRes<Vec<u8>> {
+        let bytes = self.write_to_vec_with_ctx(endianness)
+            .map_err(StorageUtilError::SpeedyWriteFailed)?;
         Ok(bytes)
     }
 
-    async fn with_binz(self) -> AllocPerfRes<(Self, Vec<u8>)> {
+    async fn with_binz(self, opts: WriteOpts) -> AllocPerfRes<(Self, Vec<u8>)> {
         blocking::unblock(move || {
-            let serialized = self.to_serialized_blocking()?;
+            let WriteOpts { codec, endianness } = opts;
+            let serialized = self.to_serialized_blocking(endianness.into())?;
             let mut binz = Vec::with_capacity(serialized.len() / 2);
-            let mut comp = FrameEncoder::new(&mut binz);
-            comp.write_all(&*serialized)
-                .map_err(|source| StorageUtilError::Compress(source))?;
-            comp.flush()
-                .map_err(|source| StorageUtilError::Compress(source))?;
-            comp.finish()
-                .map_err(|source| StorageUtilError::CompressFinish(source))?;
+            StorageHeader::new(Self::FILE_NAME, endianness.into(), codec, &serialized).write_to(&mut binz);
+            match codec {
+                Codec::None => binz.extend_from_slice(&serialized),
+                Codec::Lz4Frame => {
+                    let mut comp = FrameEncoder::new(&mut binz);
+                    comp.write_all(&serialized)
+                        .map_err(|source| StorageUtilError::Compress { codec, source })?;
+                    comp.flush()
+                        .map_err(|source| StorageUtilError::Compress { codec, source })?;
+                    comp.finish()
+                        .map_err(StorageUtilError::CompressFinish)?;
+                },
+                Codec::Lz4Block => binz.extend_from_slice(&lz4_flex::block::compress(&serialized)),
+                Codec::Deflate => {
+                    let mut comp = DeflateEncoder::new(&mut binz, Compression::default());
+                    comp.write_all(&serialized)
+                        .map_err(|source| StorageUtilError::Compress { codec, source })?;
+                    comp.finish()
+                        .map_err(|source| StorageUtilError::Compress { codec, source })?;
+                },
+            }
             Ok((self, binz))
         }).await
     }
@@ -111,30 +621,59 @@ pub(crate) trait StorageOpsSpeedy: StorageInfo + IsSpeedyRwRd {
         Ok(sub_dir_path.join(Self::FILE_NAME))
     }
 
-    async fn from_binz_file(path: impl AsRef<Path>) -> AllocPerfRes<Self> {
-        tracing::debug!("getting {desc} from binz file @ {path_str:?}",
+    async fn from_binz_file(path: impl AsRef<Path>, load_mode: LoadMode, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
+        tracing::debug!("getting {desc} from binz file @ {path_str:?} ({load_mode:?})",
             desc=Self::DESC,
             path_str=path.as_ref().to_string_lossy());
 
-        let binz_bytes = ExistentReadableFile::open(path)
-            .await?
-            .read()
-            .await?;
-        Self::from_binz(binz_bytes).await
+        match load_mode {
+            LoadMode::Read => {
+                let binz_bytes = stats.record_async(Phase::Read, async {
+                    ExistentReadableFile::open(path)
+                        .await?
+                        .read()
+                        .await
+                }).await?;
+                Self::from_binz(binz_bytes, stats).await
+            },
+            LoadMode::Stream => Self::from_binz_file_streamed(path.as_ref().to_owned(), stats).await,
+            LoadMode::Mmap => {
+                // pages are only read when touched, so most of the reading is recorded in later phases
+                let mapped = stats.record_async(Phase::Read, async {
+                    ExistentReadableFile::open(path)
+                        .await?
+                        .mmap()
+                }).await?;
+                Self::from_mapped(mapped, stats).await
+            },
+        }
+    }
+
+    /// Map and validate the local file of `sub`, so views of its data can be deserialized with `MappedBinz::view()`.
+    async fn map_local(sub: &SubFull, stats: &mut PhaseStats) -> AllocPerfRes<MappedBinz<Self>>
+        where Self: IsSpeedyView,
+    {
+        let save_path = Self::get_path(sub).await?;
+        tracing::info!("map {desc} of account {idx} from {dom} using file @ '{save_path_str}'",
+            desc=Self::DESC,
+            save_path_str=save_path.to_string_lossy(),
+            idx=sub.idx,
+            dom=sub.domain);
+        Self::map_binz_file(save_path, stats).await
     }
 
-    async fn from_local(sub: &SubFull) -> AllocPerfRes<Self> {
+    async fn from_local(sub: &SubFull, load_mode: LoadMode, stats: &mut PhaseStats) -> AllocPerfRes<Self> {
         let save_path = Self::get_path(sub).await?;
         tracing::info!("get {desc} of account {idx} from {dom} using file @ '{save_path_str}'",
             desc=Self::DESC,
             save_path_str=save_path.to_string_lossy(),
             idx=sub.idx,
             dom=sub.domain);
-        Ok(Self::from_binz_file(save_path).await?)
+        Ok(Self::from_binz_file(save_path, load_mode, stats).await?)
     }
 
-    async fn with_updated_binz_file(self, path: impl AsRef<Path>) -> AllocPerfRes<(Self, UpdatedOrRolledBack)> {
-        let (self_, binz) = self.with_binz().await?;
+    async fn with_updated_binz_file(self, path: impl AsRef<Path>, opts: WriteOpts) -> AllocPerfRes<(Self, UpdatedOrRolledBack)> {
+        let (self_, binz) = self.with_binz(opts).await?;
 
         let updatable_f = UpdatableWritableFile::update_or_create(&path).await?;
 
@@ -143,9 +682,179 @@ pub(crate) trait StorageOpsSpeedy: StorageInfo + IsSpeedyRwRd {
             desc=Self::DESC,
             path_str=path.as_ref().to_string_lossy());
 
-        Ok((self_, updatable_f.update_or_rollback(&*binz).await?))
+        Ok((self_, updatable_f.update_or_rollback(&binz).await?))
+    }
+
+    /// Re-write the file at `path` with `opts`.
+    ///
+    /// Files written before storage headers were added are accepted too.
+    async fn convert_binz_file(path: impl AsRef<Path>, opts: WriteOpts) -> AllocPerfRes<()> {
+        let path = path.as_ref();
+        // files of interrupted runs may only be left as backups
+        UpdatableWritableFile::recover_interrupted(path).await?;
+        let binz = ExistentReadableFile::open(path)
+            .await?
+            .read()
+            .await?;
+
+        let val = {
+            let path = path.to_owned();
+            blocking::unblock(move || {
+                match StorageHeader::read_from(&binz) {
+                    Err(StorageUtilError::MissingHeader) => {
+                        tracing::info!("{desc} @ '{path_str}' has no storage header, reading as legacy data",
+                            desc=Self::DESC,
+                            path_str=path.to_string_lossy());
+                        Self::from_legacy_binz_blocking(binz)
+                    },
+                    _ => {
+                        let (header, serialized) = Self::serialized_from_binz_blocking(binz)?;
+                        Self::from_serialized_blocking(serialized, header.endianness)
+                    },
+                }
+            }).await?
+        };
+
+        tracing::info!("converting {desc} @ '{path_str}' to {opts:?}",
+            desc=Self::DESC,
+            path_str=path.to_string_lossy());
+        let _ = val.with_updated_binz_file(path, opts).await?;
+        Ok(())
     }
 }
 
 impl<T> StoragePrivSpeedy for T where T: StorageInfo + IsSpeedyRwRd {}
 impl<T> StorageOpsSpeedy for T where T: StorageInfo + IsSpeedyRwRd + StoragePrivSpeedy {}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+    use crate::AllocPerfError;
+
+    #[derive(Readable, Writable, PartialEq, Debug)]
+    struct TestData {
+        num: u64,
+        name: String,
+        list: Vec<u32>,
+    }
+
+    impl StorageInfo for TestData {
+        const DESC: &'static str = "test data";
+    }
+
+    impl IsSpeedyRwRd for TestData {
+        const FILE_NAME: &'static str = "TEST";
+    }
+
+    fn test_data() -> TestData {
+        TestData { num: 0x0102030405060708, name: "test".into(), list: vec![1, 2, 3] }
+    }
+
+    fn tmp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("alloc-perf-test-{}-{name}", std::process::id()))
+    }
+
+    fn header(codec: Codec, serialized: &[u8]) -> StorageHeader {
+        StorageHeader::new(TestData::FILE_NAME, Endianness::BigEndian, codec, serialized)
+    }
+
+    fn header_bytes(header: &StorageHeader) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let serialized = test_data().write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let written = header(Codec::Lz4Block, &serialized);
+        let mut bytes = header_bytes(&written);
+        bytes.extend_from_slice(b"payload");
+
+        let (read, rest) = StorageHeader::read_from(&bytes).unwrap();
+        assert_eq!(rest, b"payload");
+        assert_eq!(read.version, StorageHeader::VERSION);
+        assert_eq!(read.type_id, TestData::FILE_NAME);
+        assert_eq!(read.endianness, Endianness::BigEndian);
+        assert_eq!(read.codec, Codec::Lz4Block);
+        assert_eq!(read.uncompressed_len, serialized.len() as u64);
+        assert_eq!(read.checksum, written.checksum);
+        read.validate_payload(&serialized).unwrap();
+
+        let streamed = StorageHeader::read_from_stream(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(streamed.checksum, written.checksum);
+    }
+
+    #[test]
+    fn header_errors() {
+        let bytes = header_bytes(&header(Codec::None, b"serialized"));
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(StorageHeader::read_from(&wrong_magic), Err(StorageUtilError::MissingHeader)));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[StorageHeader::MAGIC.len()..][..2].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            StorageHeader::read_from(&wrong_version),
+            Err(StorageUtilError::UnsupportedVersion { found: 2, supported: StorageHeader::VERSION })
+        ));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(StorageHeader::read_from(truncated), Err(StorageUtilError::TruncatedHeader)));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let serialized = test_data().write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let mut binz = header_bytes(&header(Codec::None, &serialized));
+        binz.extend_from_slice(&serialized);
+        *binz.last_mut().unwrap() ^= 1;
+
+        let res = TestData::serialized_from_binz_blocking(binz);
+        assert!(matches!(res, Err(AllocPerfError::StorageUtil(StorageUtilError::ChecksumMismatch { .. }))));
+    }
+
+    #[test]
+    fn lz4_block_len() {
+        let serialized = test_data().write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let compressed = lz4_flex::block::compress(&serialized);
+        let decompressed = TestData::decompress_blocking(&header(Codec::Lz4Block, &serialized), &compressed).unwrap();
+        assert_eq!(decompressed, serialized);
+
+        // a corrupted length must be rejected before it's used to allocate the output buffer
+        let mut oversized = header(Codec::Lz4Block, &serialized);
+        oversized.uncompressed_len = u64::MAX;
+        let res = TestData::decompress_blocking(&oversized, &compressed);
+        assert!(matches!(
+            res,
+            Err(AllocPerfError::StorageUtil(StorageUtilError::Lz4BlockLenImpossible { uncompressed_len: u64::MAX, .. }))
+        ));
+    }
+
+    #[test]
+    fn convert_legacy_binz() {
+        let val = test_data();
+        let mut legacy = Vec::new();
+        let mut comp = FrameEncoder::new(&mut legacy);
+        comp.write_all(&val.write_to_vec_with_ctx(LEGACY_ENDIANNESS).unwrap()).unwrap();
+        comp.finish().unwrap();
+
+        let path = tmp_path("legacy");
+        std::fs::write(&path, &legacy).unwrap();
+        let opts = WriteOpts { codec: Codec::None, endianness: DataEndianness::Big };
+        let convert_res = block_on(TestData::convert_binz_file(&path, opts));
+        let info_res = block_on(BinzFileInfo::from_binz_file(&path));
+        let converted_res = block_on(TestData::from_binz_file(&path, LoadMode::Read, &mut PhaseStats::default()));
+        let _ = std::fs::remove_file(&path);
+
+        convert_res.unwrap();
+        let info = info_res.unwrap();
+        assert_eq!(info.type_id, TestData::FILE_NAME);
+        assert_eq!(info.endianness, Endianness::BigEndian);
+        assert_eq!(info.codec, Codec::None);
+        assert_eq!(converted_res.unwrap(), val);
+    }
+}
@@ -17,6 +17,7 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use speedy::{Context, Readable, Reader, Writable, Writer};
 use thiserror::Error;
 
@@ -35,6 +36,22 @@ pub struct NDWrapper(
     pub NaiveDate
 );
 
+/// Same `%Y-%m-%d` format as stored, so dates survive JSON round trips.
+impl Serialize for NDWrapper {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0.format("%Y-%m-%d"))
+    }
+}
+
+impl<'de> Deserialize<'de> for NDWrapper {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let nd = NaiveDate::parse_from_str(&s, "%Y-%m-%d")
+            .map_err(|_| de::Error::custom(WrapperTypesError::InvalidNaiveDateString(s)))?;
+        Ok(Self(nd))
+    }
+}
+
 impl<'a, C: Context> Readable<'a, C> for NDWrapper {
     #[inline]
     fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result< Self, C::Error> {